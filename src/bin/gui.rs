@@ -2,7 +2,11 @@
 use iced::alignment;
 use iced::executor;
 use iced::theme::Theme;
-use iced::widget::{button, column, container, horizontal_space, pick_list, row, text, text_input};
+use iced::widget::image;
+use iced::widget::{
+    button, checkbox, column, container, horizontal_space, pick_list, row, scrollable, text,
+    text_input,
+};
 use iced::{window, Application, Color, Command, Element, Length, Settings};
 
 use native_dialog::{FileDialog, MessageDialog, MessageType};
@@ -14,15 +18,27 @@ use fast_log::plugin::packer::GZipPacker;
 use log::{error, LevelFilter};
 
 use std::env;
+use std::fmt;
 
 use utillib::load as config_load;
 use utillib::save_config;
-use utillib::Timetype;
+use utillib::PlaylistItem;
+use utillib::{bookmark::*, push_command};
+use utillib::{ffprobe_exe, format_timecode, generate_chapters};
+use utillib::{movie_title, recent_history, HistoryEntry};
+use utillib::{profile_args, profile_name, set_profile};
+use utillib::{EndPolicy, Timetype};
 
 pub fn main() -> iced::Result {
+    // the same profile as the program that started us
+    if let (Some(name), _) = profile_args(env::args().skip(1).collect()) {
+        if let Err(e) = set_profile(&name) {
+            error!("Select profile error! Error:{}", e);
+        }
+    }
     SlowMovie::run(Settings {
         window: window::Settings {
-            size: (800, 400),
+            size: (800, 980),
             ..window::Settings::default()
         },
         ..Settings::default()
@@ -38,6 +54,65 @@ struct SlowMovie {
     time_type: Timetype,
     frame_time_type: Timetype,
     change_flag: bool,
+    // the start time was edited, confirm moves playback there
+    frame_changed: bool,
+    // exact frame of a bookmark or history jump, the start time only shows its seconds
+    jump_frame: Option<u64>,
+    playlist: Vec<PlaylistItem>,
+    end_policy: EndPolicy,
+    shuffle: bool,
+    repeat: bool,
+    chapters: Vec<ChapterChoice>,
+    chapter: Option<ChapterChoice>,
+    bookmarks: Vec<Bookmark>,
+    bookmark: Option<Bookmark>,
+    bookmark_name: String,
+    bookmark_note: String,
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChapterChoice {
+    index: usize,
+    start_ms: u64,
+    title: String,
+}
+
+impl fmt::Display for ChapterChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title = match self.title.is_empty() {
+            true => format!("Chapter {}", self.index + 1),
+            false => self.title.clone(),
+        };
+        write!(f, "{} ({})", title, format_timecode(self.start_ms))
+    }
+}
+
+fn error_dialog(text: &str) {
+    let _result = MessageDialog::new()
+        .set_title("Error")
+        .set_text(text)
+        .set_type(MessageType::Error)
+        .show_alert();
+}
+
+fn load_chapters(movie_path: &str) -> Vec<ChapterChoice> {
+    let chapters = ffprobe_exe().and_then(|ffprobe| generate_chapters(&ffprobe, movie_path));
+    match chapters {
+        Ok(chapters) => chapters
+            .into_iter()
+            .enumerate()
+            .map(|(index, c)| ChapterChoice {
+                index,
+                start_ms: c.start_ms,
+                title: c.title,
+            })
+            .collect(),
+        Err(e) => {
+            log::info!("Read chapters of {} failed. Error:{}", movie_path, e);
+            Vec::new()
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -47,10 +122,49 @@ enum Message {
     TimeInputChanged(String),
     FrameInputChanged(String),
     ButtonSelect,
+    ButtonSelectFolder,
+    AddPlaylist,
+    RemovePlaylist(usize),
+    MovePlaylistUp(usize),
+    MovePlaylistDown(usize),
+    SetEndPolicy(EndPolicy),
+    SetShuffle(bool),
+    SetRepeat(bool),
+    SetChapter(ChapterChoice),
+    SetBookmark(Bookmark),
+    BookmarkNameChanged(String),
+    BookmarkNoteChanged(String),
+    AddBookmark,
+    JumpBookmark,
+    RemoveBookmark,
+    ExportBookmarks,
+    ImportBookmarks,
+    ShowHistory(usize),
+    RefreshHistory,
     Confirm,
     Exit,
 }
 
+impl SlowMovie {
+    // The wallpaper loop makes the jump, the fields follow so confirm keeps it.
+    fn follow_jump(&mut self, movie_path: String, frame: u64, time_ms: u64) {
+        self.frame_changed = true;
+        self.jump_frame = Some(frame);
+        self.change_flag = false;
+        let secs = time_ms / 1000;
+        self.frame_str = match self.frame_time_type {
+            Timetype::Minute => (secs / 60).to_string(),
+            Timetype::Hour => (secs / 60 / 60).to_string(),
+            _ => secs.to_string(),
+        };
+        if self.movie_path != movie_path {
+            self.movie_path = movie_path;
+            self.chapters = load_chapters(&self.movie_path);
+            self.chapter = None;
+        }
+    }
+}
+
 impl Application for SlowMovie {
     type Executor = executor::Default;
     type Message = Message;
@@ -64,7 +178,7 @@ impl Application for SlowMovie {
                 .chan_len(Some(100000))
                 .level(LevelFilter::Debug)
                 .file_split(
-                    "logs/", // current_exe dir
+                    &utillib::log_dir(), // current_exe dir
                     LogSize::MB(5),
                     RollingType::KeepNum(5),
                     GZipPacker {},
@@ -112,11 +226,28 @@ impl Application for SlowMovie {
             _ => (frame_count / 24).to_string(),
         };
         log::debug!("Frame Count from config file:{}", data.frame_str);
+        data.playlist = cur_config.get_playlist();
+        data.end_policy = cur_config.get_end_policy();
+        data.shuffle = cur_config.is_shuffle();
+        data.repeat = cur_config.is_repeat();
+        log::debug!("Playlist from config file:{} movies", data.playlist.len());
+        data.chapters = load_chapters(&data.movie_path);
+        data.history = recent_history(&cur_config, 20, "");
+        data.bookmarks = match load_bookmarks(&cur_config) {
+            Ok(b) => b,
+            Err(e) => {
+                log::warn!("Read bookmarks failed. Error:{}", e);
+                Vec::new()
+            }
+        };
         (data, Command::none())
     }
 
     fn title(&self) -> String {
-        String::from("简帧")
+        match profile_name().is_empty() {
+            true => String::from("简帧"),
+            false => format!("简帧 - {}", profile_name()),
+        }
     }
 
     fn update(&mut self, message: Message) -> Command<Message> {
@@ -154,18 +285,231 @@ impl Application for SlowMovie {
                 };
                 self.movie_path = movie_file.display().to_string();
                 self.change_flag = true;
+                self.jump_frame = None;
+                self.chapters = load_chapters(&self.movie_path);
+                self.chapter = None;
+            }
+            Message::ButtonSelectFolder => {
+                let cur_path = match env::current_dir() {
+                    Ok(path) => path,
+                    Err(e) => {
+                        error!("Read current dir failed! Reason:{}", e);
+                        return Command::none();
+                    }
+                };
+                match FileDialog::new()
+                    .set_location(&cur_path)
+                    .show_open_single_dir()
+                {
+                    Ok(Some(dir)) => {
+                        self.movie_path = dir.display().to_string();
+                        self.change_flag = true;
+                        self.jump_frame = None;
+                        self.chapters.clear();
+                        self.chapter = None;
+                    }
+                    Ok(None) => {
+                        log::info!("User chosed folder path is none.");
+                    }
+                    Err(e) => {
+                        log::info!("User didend choose any folder. Error:{}", e);
+                    }
+                }
+            }
+            Message::AddPlaylist => {
+                let cur_path = match env::current_dir() {
+                    Ok(path) => path,
+                    Err(e) => {
+                        error!("Read current dir failed! Reason:{}", e);
+                        return Command::none();
+                    }
+                };
+                match FileDialog::new()
+                    .set_location(&cur_path)
+                    .show_open_multiple_file()
+                {
+                    Ok(files) => {
+                        // a new playlist should move on, looping would keep its first movie
+                        if self.playlist.is_empty()
+                            && !files.is_empty()
+                            && self.end_policy == EndPolicy::Loop
+                        {
+                            self.end_policy = EndPolicy::NextMovie;
+                        }
+                        for f in files {
+                            self.playlist
+                                .push(PlaylistItem::new(f.display().to_string()));
+                        }
+                    }
+                    Err(e) => {
+                        log::info!("User didend choose any file. Error:{}", e);
+                        return Command::none();
+                    }
+                }
+            }
+            Message::RemovePlaylist(index) => {
+                if index < self.playlist.len() {
+                    self.playlist.remove(index);
+                }
+            }
+            Message::MovePlaylistUp(index) => {
+                if index > 0 && index < self.playlist.len() {
+                    self.playlist.swap(index - 1, index);
+                }
+            }
+            Message::MovePlaylistDown(index) => {
+                if index + 1 < self.playlist.len() {
+                    self.playlist.swap(index, index + 1);
+                }
+            }
+            Message::SetEndPolicy(policy) => {
+                self.end_policy = policy;
+            }
+            Message::SetShuffle(shuffle) => {
+                self.shuffle = shuffle;
+            }
+            Message::SetRepeat(repeat) => {
+                self.repeat = repeat;
+            }
+            Message::SetChapter(chapter) => {
+                let secs = chapter.start_ms / 1000;
+                self.frame_str = match self.frame_time_type {
+                    Timetype::Minute => (secs / 60).to_string(),
+                    Timetype::Hour => (secs / 60 / 60).to_string(),
+                    _ => secs.to_string(),
+                };
+                self.chapter = Some(chapter);
+                self.frame_changed = true;
+                self.jump_frame = None;
+            }
+            Message::SetBookmark(bookmark) => {
+                self.bookmark = Some(bookmark);
+            }
+            Message::BookmarkNameChanged(value) => {
+                self.bookmark_name = value;
+            }
+            Message::BookmarkNoteChanged(value) => {
+                self.bookmark_note = value;
+            }
+            Message::AddBookmark => {
+                let name = self.bookmark_name.trim().to_owned();
+                if name.is_empty() {
+                    error_dialog("Input a name for the bookmark! ");
+                    return Command::none();
+                }
+                let conf = config_load();
+                match bookmark_here(&conf, &name, self.bookmark_note.trim()) {
+                    Ok(b) => {
+                        add_bookmark(&mut self.bookmarks, b.clone());
+                        if let Err(e) = save_bookmarks(&conf, &self.bookmarks) {
+                            log::error!("Save bookmarks failed. Error:{}", e);
+                            error_dialog("Save bookmarks failed! ");
+                        }
+                        self.bookmark = Some(b);
+                        self.bookmark_name.clear();
+                        self.bookmark_note.clear();
+                    }
+                    Err(e) => {
+                        log::error!("Bookmark current frame failed. Error:{}", e);
+                        error_dialog("Bookmark current frame failed! ");
+                    }
+                }
+            }
+            Message::JumpBookmark => {
+                let bookmark = match &self.bookmark {
+                    Some(b) => b.clone(),
+                    None => return Command::none(),
+                };
+                if let Err(e) = push_command(&config_load(), bookmark.jump_command()) {
+                    log::error!("Send jump command failed. Error:{}", e);
+                    error_dialog("Jump to bookmark failed! ");
+                    return Command::none();
+                }
+                self.follow_jump(bookmark.movie_path, bookmark.frame, bookmark.time_ms);
+            }
+            Message::RefreshHistory => {
+                self.history = recent_history(&config_load(), 20, "");
+            }
+            Message::ShowHistory(index) => {
+                let entry = match self.history.get(index) {
+                    Some(e) => e.clone(),
+                    None => return Command::none(),
+                };
+                if let Err(e) = push_command(&config_load(), entry.jump_command()) {
+                    log::error!("Send jump command failed. Error:{}", e);
+                    error_dialog("Show frame again failed! ");
+                    return Command::none();
+                }
+                self.follow_jump(entry.movie_path, entry.frame, entry.time_ms);
+            }
+            Message::RemoveBookmark => {
+                let bookmark = match self.bookmark.take() {
+                    Some(b) => b,
+                    None => return Command::none(),
+                };
+                self.bookmarks.retain(|b| b.name != bookmark.name);
+                if let Err(e) = save_bookmarks(&config_load(), &self.bookmarks) {
+                    log::error!("Save bookmarks failed. Error:{}", e);
+                    error_dialog("Save bookmarks failed! ");
+                }
+            }
+            Message::ExportBookmarks => {
+                match FileDialog::new()
+                    .add_filter("JSON", &["json"])
+                    .show_save_single_file()
+                {
+                    Ok(Some(path)) => {
+                        if let Err(e) =
+                            export_bookmarks(&self.bookmarks, &path.display().to_string())
+                        {
+                            log::error!("Export bookmarks failed. Error:{}", e);
+                            error_dialog("Export bookmarks failed! ");
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::info!("User didend choose any file. Error:{}", e);
+                    }
+                }
+            }
+            Message::ImportBookmarks => {
+                match FileDialog::new()
+                    .add_filter("JSON", &["json"])
+                    .show_open_single_file()
+                {
+                    Ok(Some(path)) => {
+                        let conf = config_load();
+                        match import_bookmarks(&mut self.bookmarks, &path.display().to_string())
+                            .and_then(|_| save_bookmarks(&conf, &self.bookmarks))
+                        {
+                            Ok(_) => {}
+                            Err(e) => {
+                                log::error!("Import bookmarks failed. Error:{}", e);
+                                error_dialog("Import bookmarks failed! ");
+                            }
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::info!("User didend choose any file. Error:{}", e);
+                    }
+                }
             }
             Message::SetTime(timetype) => {
                 self.time_type = timetype;
             }
             Message::SetFrameTime(timetype) => {
                 self.frame_time_type = timetype;
+                self.frame_changed = true;
+                self.jump_frame = None;
             }
             Message::TimeInputChanged(value) => {
                 self.time_str = value;
             }
             Message::FrameInputChanged(value) => {
                 self.frame_str = value;
+                self.frame_changed = true;
+                self.jump_frame = None;
             }
             Message::Confirm => {
                 let mut conf = config_load();
@@ -205,11 +549,42 @@ impl Application for SlowMovie {
                         return Command::none();
                     }
                 };
+                // a movie chosen above joins the playlist, so the playlist plays it next
+                if self.change_flag
+                    && !self.playlist.is_empty()
+                    && !self
+                        .playlist
+                        .iter()
+                        .any(|item| item.path == self.movie_path)
+                {
+                    self.playlist
+                        .push(PlaylistItem::new(self.movie_path.clone()));
+                }
+                // keep pointing at the same movie after the list was edited
+                let current = match self
+                    .playlist
+                    .iter()
+                    .position(|item| item.path == self.movie_path)
+                {
+                    Some(i) => Some(i),
+                    None => conf.current_playlist_item().and_then(|cur| {
+                        self.playlist.iter().position(|item| item.path == cur.path)
+                    }),
+                };
+                conf.set_playlist(self.playlist.clone());
+                conf.set_playlist_index(current.unwrap_or(0));
+                conf.set_end_policy(self.end_policy);
+                conf.set_shuffle(self.shuffle);
+                conf.set_repeat(self.repeat);
                 conf.set_time_interval(time);
                 conf.set_time_type(self.time_type);
                 conf.set_frame_time_type(self.frame_time_type);
                 conf.set_exit_flag(false);
-                conf.set_frame_count(frame_sec * 24);
+                // the slideshow keeps its place unless a start time or movie was chosen
+                if self.frame_changed || self.change_flag {
+                    conf.set_frame_count(self.jump_frame.unwrap_or(frame_sec * 24));
+                    conf.set_reverse(false);
+                }
                 save_config(&conf);
                 return window::close();
             }
@@ -235,6 +610,9 @@ impl Application for SlowMovie {
         let select_file_button = button("Choose a Movie")
             .padding(10)
             .on_press(Message::ButtonSelect);
+        let select_folder_button = button("Frames Folder")
+            .padding(10)
+            .on_press(Message::ButtonSelectFolder);
 
         let time_label = text("Time interval:").size(30);
         let time_input = text_input(
@@ -266,14 +644,147 @@ impl Application for SlowMovie {
         .placeholder("Choose a Timetype...")
         .text_size(30);
 
+        let chapter_pick_list = pick_list(
+            self.chapters.clone(),
+            self.chapter.clone(),
+            Message::SetChapter,
+        )
+        .placeholder("Chapter...")
+        .text_size(20);
+
+        let bookmark_label = text("Bookmarks:").size(30);
+        let bookmark_pick_list = pick_list(
+            self.bookmarks.clone(),
+            self.bookmark.clone(),
+            Message::SetBookmark,
+        )
+        .placeholder("Choose a bookmark...")
+        .text_size(20);
+        let jump_button = button("Jump").padding(10).on_press(Message::JumpBookmark);
+        let remove_bookmark_button = button("Remove")
+            .padding(10)
+            .on_press(Message::RemoveBookmark);
+        let export_button = button("Export")
+            .padding(10)
+            .on_press(Message::ExportBookmarks);
+        let import_button = button("Import")
+            .padding(10)
+            .on_press(Message::ImportBookmarks);
+        let bookmark_name_input = text_input(
+            "Bookmark name ... ",
+            &self.bookmark_name,
+            Message::BookmarkNameChanged,
+        )
+        .padding(10)
+        .size(20);
+        let bookmark_note_input = text_input(
+            "Note ... ",
+            &self.bookmark_note,
+            Message::BookmarkNoteChanged,
+        )
+        .padding(10)
+        .size(20);
+        let add_bookmark_button = button("Bookmark Current Frame")
+            .padding(10)
+            .on_press(Message::AddBookmark);
+
+        let playlist_label = text("Playlist:").size(30);
+        let add_button = button("Add Movies")
+            .padding(10)
+            .on_press(Message::AddPlaylist);
+        let end_pick_list = pick_list(
+            &EndPolicy::ALL[..],
+            Some(self.end_policy),
+            Message::SetEndPolicy,
+        )
+        .placeholder("At the end...")
+        .text_size(20);
+        let shuffle_box = checkbox("Shuffle", self.shuffle, Message::SetShuffle);
+        let repeat_box = checkbox("Repeat", self.repeat, Message::SetRepeat);
+
+        let playlist_rows = column(
+            self.playlist
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    row![
+                        text(&item.path).size(20).width(Length::Fill),
+                        button("Up").on_press(Message::MovePlaylistUp(i)),
+                        button("Down").on_press(Message::MovePlaylistDown(i)),
+                        button("Remove").on_press(Message::RemovePlaylist(i)),
+                    ]
+                    .spacing(10)
+                    .into()
+                })
+                .collect(),
+        )
+        .spacing(5);
+        let playlist_view = scrollable(playlist_rows).height(Length::Units(150));
+
+        let history_label = text("Recently shown:").size(30);
+        let refresh_button = button("Refresh")
+            .padding(10)
+            .on_press(Message::RefreshHistory);
+        let history_rows = column(
+            self.history
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    row![
+                        image(image::Handle::from_path(&entry.thumbnail_path))
+                            .width(Length::Units(96)),
+                        text(format!(
+                            "{}  {}  {}",
+                            entry.shown_at,
+                            movie_title(&entry.movie_path),
+                            format_timecode(entry.time_ms)
+                        ))
+                        .size(20)
+                        .width(Length::Fill),
+                        button("Show").on_press(Message::ShowHistory(i)),
+                    ]
+                    .spacing(10)
+                    .into()
+                })
+                .collect(),
+        )
+        .spacing(5);
+        let history_view = scrollable(history_rows).height(Length::Units(140));
+
         let ok_button = button("confirm").padding(10).on_press(Message::Confirm);
         let exit_button = button("exit").padding(10).on_press(Message::Exit);
 
         let content = column![
             title,
-            row![select_file_button, filepath_input].spacing(10),
+            row![select_file_button, select_folder_button, filepath_input].spacing(10),
             row![time_label, time_input, time_pick_list].spacing(10),
-            row![frame_label, frame_input, frame_pick_list].spacing(10),
+            row![frame_label, frame_input, frame_pick_list, chapter_pick_list].spacing(10),
+            row![
+                playlist_label,
+                add_button,
+                end_pick_list,
+                shuffle_box,
+                repeat_box
+            ]
+            .spacing(10),
+            playlist_view,
+            row![
+                bookmark_label,
+                bookmark_pick_list,
+                jump_button,
+                remove_bookmark_button,
+                export_button,
+                import_button
+            ]
+            .spacing(10),
+            row![
+                bookmark_name_input,
+                bookmark_note_input,
+                add_bookmark_button
+            ]
+            .spacing(10),
+            row![history_label, refresh_button].spacing(10),
+            history_view,
             row![horizontal_space(Length::Fill), ok_button, exit_button].spacing(10),
         ]
         .spacing(20)
@@ -0,0 +1,31 @@
+// Console front end for the command line. slow_movie.exe has no console, so the lists,
+// progress bars and prompts of its subcommands would go nowhere.
+#[path = "../cli.rs"]
+mod cli;
+
+use std::env;
+use std::process::Command;
+
+use utillib::{config, control, profile, Errors};
+
+pub fn main() -> Result<(), Errors> {
+    let (name, args) = profile::profile_args(env::args().skip(1).collect());
+    if let Some(name) = name {
+        profile::set_profile(&name)?;
+    }
+    if let Some(result) = cli::run(&args) {
+        return result;
+    }
+
+    // anything else is for the wallpaper program, running or not
+    let lock = named_lock::NamedLock::create(&profile::lock_name())?;
+    let running = matches!(lock.try_lock(), Err(named_lock::Error::WouldBlock));
+    if running {
+        return control::push_command(&config::load(), cli::handoff_command(&args));
+    }
+    let mut program = env::current_exe()?;
+    program.pop();
+    program.push("slow_movie.exe");
+    Command::new(program).args(env::args().skip(1)).spawn()?;
+    Ok(())
+}
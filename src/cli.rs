@@ -0,0 +1,267 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use utillib::{
+    bookmark::*, config, control::*, export::*, format_timecode, history::*, movie_title,
+    prerender::*, timelapse::*, Errors,
+};
+
+const USAGE: &str = "Usage: slowmovie [--profile <name>] ...
+       slowmovie [next | prev | pause | resume | seek <frame> | next-chapter | prev-chapter | goto-chapter <n, from 1>]
+       slowmovie bookmark [add <name> [note] | remove <name> | list | goto <name> | export <file> | import <file>]
+       slowmovie prerender [step] [movie] [--yes]
+       slowmovie history [count] [movie filter] | history show <n>
+       slowmovie export [sheet | barcode] [count] [movie] [out.png]
+       slowmovie timelapse <out.mp4 | out.gif> [history [count] | range <first> <end> <step> [movie]] [--fps <n>]
+       slowmovie [settings | <movie file>]";
+
+fn usage_error(args: &[String]) -> Errors {
+    println!("{}", USAGE);
+    Errors::ControlError(format!("Unknown command \"{}\"", args.join(" ")))
+}
+
+/// Handle command line arguments. None means there were none and the program starts as usual.
+pub fn run(args: &[String]) -> Option<Result<(), Errors>> {
+    let name = args.first()?;
+    match name.as_str() {
+        "bookmark" => return Some(run_bookmark(args)),
+        "history" => return Some(run_history(args)),
+        "export" => return Some(run_export(args)),
+        "timelapse" => return Some(run_timelapse(args)),
+        "prerender" => return Some(run_prerender(args)),
+        _ => {}
+    }
+    // the running instance picks the command up at its next check
+    if let Some(command) = ControlCommand::parse(name, args.get(1).map(|a| a.as_str())) {
+        let conf = config::load();
+        return Some(push_command(&conf, command));
+    }
+    // anything else starts the program, or goes to the running instance through
+    // handoff_command; a movie named like a subcommand needs a path, like .\next
+    if name == "settings" || Path::new(name).exists() {
+        return None;
+    }
+    Some(Err(usage_error(args)))
+}
+
+/// What a started copy asks of the instance already running: show the settings window,
+/// or switch to the movie it was opened with.
+pub fn handoff_command(args: &[String]) -> ControlCommand {
+    match args.first() {
+        Some(path) if path != "settings" && Path::new(path).exists() => {
+            let path = std::fs::canonicalize(path)
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| path.clone());
+            // canonicalize gives \\?\ paths, ffmpeg and the config want plain ones
+            let path = path.strip_prefix(r"\\?\").unwrap_or(&path).to_owned();
+            ControlCommand::Open { movie_path: path }
+        }
+        _ => ControlCommand::ShowSettings,
+    }
+}
+
+fn run_bookmark(args: &[String]) -> Result<(), Errors> {
+    let conf = config::load();
+    let mut bookmarks = load_bookmarks(&conf)?;
+    let action = args.get(1).map(|a| a.as_str()).unwrap_or("list");
+    let value = args.get(2).map(|a| a.as_str());
+    match (action, value) {
+        ("list", _) => {
+            for b in bookmarks.iter() {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    b.name,
+                    b.movie_path,
+                    format_timecode(b.time_ms),
+                    b.note
+                );
+            }
+            Ok(())
+        }
+        ("add", Some(name)) => {
+            let note = args[3..].join(" ");
+            add_bookmark(&mut bookmarks, bookmark_here(&conf, name, &note)?);
+            save_bookmarks(&conf, &bookmarks)
+        }
+        ("remove", Some(name)) => {
+            bookmarks.retain(|b| b.name != name);
+            save_bookmarks(&conf, &bookmarks)
+        }
+        ("goto", Some(name)) => match find_bookmark(&bookmarks, name) {
+            Some(b) => push_command(&conf, b.jump_command()),
+            None => Err(Errors::BookmarkError(format!("No bookmark named {}", name))),
+        },
+        ("export", Some(path)) => export_bookmarks(&bookmarks, path),
+        ("import", Some(path)) => {
+            let count = import_bookmarks(&mut bookmarks, path)?;
+            println!("Imported {} bookmarks.", count);
+            save_bookmarks(&conf, &bookmarks)
+        }
+        _ => Err(usage_error(args)),
+    }
+}
+
+fn run_history(args: &[String]) -> Result<(), Errors> {
+    let conf = config::load();
+    // entries are numbered from 1, the newest first
+    if args.get(1).map(|a| a.as_str()) == Some("show") {
+        let n = match args.get(2).and_then(|a| a.parse::<usize>().ok()) {
+            Some(n) if n > 0 => n,
+            _ => return Err(usage_error(args)),
+        };
+        return match recent_history(&conf, n, "").get(n - 1) {
+            Some(entry) => push_command(&conf, entry.jump_command()),
+            None => Err(Errors::HistoryError(format!("No history entry {}", n))),
+        };
+    }
+    let count = args
+        .get(1)
+        .and_then(|a| a.parse::<usize>().ok())
+        .unwrap_or(20);
+    let filter = args.get(2).map(|a| a.as_str()).unwrap_or("");
+    for (i, entry) in recent_history(&conf, count, filter).iter().enumerate() {
+        println!(
+            "{}\t{}\t{}\t{}\tframe {}\t{}",
+            i + 1,
+            entry.shown_at,
+            movie_title(&entry.movie_path),
+            format_timecode(entry.time_ms),
+            entry.frame,
+            entry.picture_path
+        );
+    }
+    Ok(())
+}
+
+fn run_export(args: &[String]) -> Result<(), Errors> {
+    let conf = config::load();
+    let kind = match args.get(1).map(|a| a.as_str()) {
+        Some(k @ ("sheet" | "barcode")) => k,
+        _ => return Err(usage_error(args)),
+    };
+    let mut count = None;
+    let mut movie_path = conf.get_movie_path();
+    let mut out_path = None;
+    for arg in args[2..].iter() {
+        if let Ok(n) = arg.parse::<u64>() {
+            count = Some(n);
+        } else if arg.to_lowercase().ends_with(".png") {
+            out_path = Some(arg.clone());
+        } else {
+            movie_path = arg.clone();
+        }
+    }
+    let out_path = out_path.unwrap_or_else(|| format!("{}_{}.png", movie_title(&movie_path), kind));
+    match kind {
+        "sheet" => export_contact_sheet(&conf, &movie_path, count.unwrap_or(24), 4, &out_path)?,
+        _ => export_barcode(
+            &conf,
+            &movie_path,
+            count.unwrap_or(1000) as u32,
+            200,
+            &out_path,
+        )?,
+    }
+    println!("Saved {}", out_path);
+    Ok(())
+}
+
+fn run_timelapse(args: &[String]) -> Result<(), Errors> {
+    let conf = config::load();
+    let out_path = match args.get(1) {
+        Some(path) => path.clone(),
+        None => return Err(usage_error(args)),
+    };
+    let mut fps = 24.0;
+    let mut rest: Vec<&str> = Vec::new();
+    let mut words = args[2..].iter();
+    while let Some(word) = words.next() {
+        if word == "--fps" {
+            fps = match words.next().and_then(|f| f.parse::<f64>().ok()) {
+                Some(f) if f > 0.0 => f,
+                _ => return Err(usage_error(args)),
+            };
+        } else {
+            rest.push(word);
+        }
+    }
+    let number = |i: usize| rest.get(i).and_then(|n| n.parse::<u64>().ok());
+    let input = match rest.first().copied() {
+        None | Some("history") => TimelapseInput::History {
+            count: number(1).unwrap_or(0) as usize,
+        },
+        Some("range") => match (number(1), number(2), number(3)) {
+            (Some(first_frame), Some(end_frame), Some(step)) => TimelapseInput::Range {
+                movie_path: rest
+                    .get(4)
+                    .map(|m| m.to_string())
+                    .unwrap_or_else(|| conf.get_movie_path()),
+                first_frame,
+                end_frame,
+                step,
+            },
+            _ => return Err(usage_error(args)),
+        },
+        _ => return Err(usage_error(args)),
+    };
+    let count = export_timelapse(&conf, &input, fps, &out_path, print_progress)?;
+    println!();
+    println!("Saved {} frames to {}", count, out_path);
+    Ok(())
+}
+
+fn run_prerender(args: &[String]) -> Result<(), Errors> {
+    let conf = config::load();
+    let mut step = 1;
+    let mut movie_path = conf.get_movie_path();
+    let mut confirmed = false;
+    for arg in args[1..].iter() {
+        match arg.as_str() {
+            "--yes" | "-y" => confirmed = true,
+            a => match a.parse::<u64>() {
+                Ok(n) => step = n,
+                Err(_) => movie_path = a.to_owned(),
+            },
+        }
+    }
+
+    let plan = plan_prerender(&conf, &movie_path, step)?;
+    println!(
+        "{}: {} of {} pictures to render, about {} MB.",
+        movie_path,
+        plan.count - plan.done,
+        plan.count,
+        plan.estimated_bytes / 1024 / 1024
+    );
+    if plan.done == plan.count {
+        return Ok(());
+    }
+    if !confirmed {
+        print!("Continue? [y/N] ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(());
+        }
+    }
+    prerender(&conf, &plan, print_progress)?;
+    println!();
+    Ok(())
+}
+
+fn print_progress(done: u64, count: u64) {
+    let width: u64 = 40;
+    let filled = match count {
+        0 => width,
+        n => (done * width / n).min(width),
+    };
+    print!(
+        "\r[{}{}] {}/{}",
+        "#".repeat(filled as usize),
+        " ".repeat((width - filled) as usize),
+        done,
+        count
+    );
+    let _ = io::stdout().flush();
+}
@@ -1,84 +1,187 @@
-use std::time::Duration;
-use std::{env, thread};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use utillib::{config, utils::*, video_process::*, Errors};
+use utillib::{
+    config, control::*, film_clock::*, frame_output::*, history::*, hooks::*, http_server::*,
+    monitor::*, palette::*, playback::*, playlist, prefetch::*, prerender::*, render::*, source::*,
+    utils::*, video_process::*, Config, Errors, FolderWatcher, PlayMode,
+};
 
 pub fn run() -> Result<(), Errors> {
+    let mut watcher: Option<FolderWatcher> = None;
+    let mut opened: Option<(String, Box<dyn FrameSource>)> = None;
+    // carries a jump to another movie over to the round that opens it
+    let mut jumped = false;
+    let mut prefetcher: Option<Prefetcher> = None;
+    let mut server: Option<FrameServer> = None;
+    let mut monitors = MonitorSet::new();
+    // wallpaper style last set for the monitor mode, None until the first frame
+    let mut shown_mode: Option<MonitorMode> = None;
+    let mut settings: Option<thread::JoinHandle<()>> = None;
+    // the settings window was just shown at startup
+    discard_commands(&config::load(), |c| *c == ControlCommand::ShowSettings);
     loop {
         let mut conf = config::load();
+        let mut loaded = config_text(&conf);
         if conf.should_exit() {
             return Ok(());
         }
-        //1. get movie frame count
-        // here we use command line to call ffmpeg to generate piticular frame
-        let ffprobe = match env::current_dir() {
-            Ok(mut path) => {
-                path.push("ffmpeg");
-                path.push("ffprobe.exe");
-                path.display().to_string()
-            }
-            Err(e) => {
-                log::error!("ffmpeg is not exist! Error:{}", e);
-                alert_dialog("Get ffprobe path failed! ");
-                return Err(Errors::InnerIOError(e));
+        update_watcher(&mut watcher, &conf);
+        update_prefetcher(&mut prefetcher, &conf);
+        update_server(&mut server, &conf);
+        if let Err(e) = playlist::prepare(&mut conf, watcher.as_ref()) {
+            log::error!("Prepare playlist error! Error:{}", e);
+            alert_dialog("Read playlist failed! ");
+            return Err(e);
+        }
+        // paused with nothing to do, the movie is not opened and probed again
+        if conf.is_paused() && !jumped && !has_commands(&conf) {
+            if let Some(s) = &server {
+                s.set_devices(conf.get_eink_devices());
+                s.set_paused(true);
+                s.set_next_frame_at(0);
             }
+            wait_for_commands(&conf, u64::from(conf.get_time_interval()) as f64);
+            continue;
+        }
+        //1. get movie frame count
+        // videos go through ffmpeg, folders of pictures are read directly
+        let movie_path = conf.get_movie_path();
+        let reuse = matches!(&opened, Some((path, _)) if *path == movie_path);
+        let mut source = match opened.take() {
+            Some((_, source)) if reuse => source,
+            _ => match open_source(
+                &conf.get_movie_path(),
+                &conf.get_image_pattern(),
+                conf.get_decode_backend(),
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    log::error!("Open movie {} error! Error:{}", conf.get_movie_path(), e);
+                    alert_dialog("Open movie failed! ");
+                    return Err(e);
+                }
+            },
         };
 
-        let total_frame = match generate_frame_count(&ffprobe, &conf.get_movie_path()) {
-            Ok(n) => n,
-            Err(e) => {
-                log::error!("Get total frame number from ffmprobe error!");
-                alert_dialog("Get total frame number failed! ");
-                return Err(e);
-            }
+        let probed = watcher
+            .as_ref()
+            .and_then(|w| w.frame_count(&conf.get_movie_path()));
+        let total_frame = match probed {
+            Some(n) => n,
+            None => match source.frame_count() {
+                Ok(n) => n,
+                Err(e) => {
+                    log::error!("Get total frame number error! Error:{}", e);
+                    alert_dialog("Get total frame number failed! ");
+                    return Err(e);
+                }
+            },
         };
         log::info!("total frames:{}", total_frame);
-
-        //2. delete old frame picture if it exist
-        log::info!("Start to delete old frame.png");
-        let frame_picture = match env::current_dir() {
-            Ok(mut path) => {
-                path.push("frame.png");
-                path.display().to_string()
-            }
-            Err(e) => {
-                log::warn!("Get current dir error! Error:{}", e);
-                return Err(Errors::InnerIOError(e));
-            }
+        let (first_frame, end_frame) = match conf.current_playlist_item() {
+            Some(item) => item.frame_range(total_frame),
+            None => (0, total_frame),
         };
 
-        match delete_file(&frame_picture) {
-            Ok(_) => {
-                log::info!("Delete old frame.png ok!");
+        // the settings were saved since the round began, start over with them
+        if changed_on_disk(&loaded) {
+            opened = Some((movie_path, source));
+            continue;
+        }
+        // commands sent from the command line since the last frame
+        let commands = take_commands(&conf);
+        let mut moved = std::mem::take(&mut jumped);
+        if !commands.is_empty() {
+            let starts: Vec<u64> = source.chapters().iter().map(|c| c.start_ms).collect();
+            let duration_ms = source.frame_time_ms(total_frame);
+            let chapters = chapter_frames(&starts, total_frame, duration_ms);
+            for command in commands.iter() {
+                if *command == ControlCommand::ShowSettings {
+                    show_settings(&mut settings);
+                }
+                log::info!("Apply control command {:?}", command);
+                moved |= apply_command(&mut conf, command, total_frame, duration_ms, &chapters);
             }
-            Err(e) => {
-                log::error!("Delete old frame.png error! Error:{}", e);
-                return Err(e);
+            conf.save();
+            loaded = config_text(&conf);
+            // a jump to another movie opens it on the next round
+            if conf.get_movie_path() != movie_path {
+                jumped = true;
+                continue;
             }
         }
+        if let Some(s) = &server {
+            s.set_devices(conf.get_eink_devices());
+            s.set_paused(conf.is_paused());
+            s.set_next_frame_at(0);
+        }
+        if conf.is_paused() && !moved {
+            wait_for_commands(&conf, u64::from(conf.get_time_interval()) as f64);
+            opened = Some((movie_path, source));
+            continue;
+        }
+
+        //2. clear a half rendered picture left by an earlier failure
+        let output =
+            match FrameOutput::new(conf.get_output_dir(), conf.get_frame_history() as usize) {
+                Ok(o) => o,
+                Err(e) => {
+                    log::error!("Create frame folder error! Error:{}", e);
+                    alert_dialog("Create frame folder failed! ");
+                    return Err(e);
+                }
+            };
+        let picture_path = output.render_path();
+        if let Err(e) = delete_file(&picture_path) {
+            log::error!("Delete old rendering.png error! Error:{}", e);
+            return Err(e);
+        }
 
         //3. convert frame to picture
-        log::info!("Start to convert frame to frame.png");
-        let ffmpeg = match env::current_dir() {
-            Ok(mut path) => {
-                path.push("ffmpeg");
-                path.push("ffmpeg.exe");
-                path.display().to_string()
-            }
-            Err(e) => {
-                log::error!("ffmpeg is not exist! Error:{}", e);
-                alert_dialog("Get ffmpeg path failed! ");
-                return Err(Errors::FfmpegLost);
+        log::info!("Start to convert frame to picture");
+        let cur_frame = match conf.get_play_mode() {
+            PlayMode::Interval => conf
+                .get_frame_count()
+                .clamp(first_frame, end_frame.saturating_sub(1).max(first_frame)),
+            PlayMode::Daily => {
+                match daily_frame_now(&conf.get_day_start(), &conf.get_day_end(), total_frame) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        log::error!("Map local time to frame error! Error:{}", e);
+                        alert_dialog("Daily window in config is invalid! ");
+                        return Err(e);
+                    }
+                }
             }
         };
 
-        let mut cur_frame = conf.get_frame_count() + 1;
-        cur_frame %= total_frame;
-
-        let mut picture_path = env::current_dir().unwrap();
-        picture_path.push("frame.png");
-        let picture_path = picture_path.display().to_string();
-        match generate_frame_picture(&ffmpeg, &conf.get_movie_path(), cur_frame, &picture_path) {
+        // the source keeps the chapters the commands above read, they are probed once a tick
+        let info =
+            FrameInfo::from_source(&conf, source.as_mut(), &movie_path, cur_frame, total_frame);
+        if let Err(e) = run_frame_hook(&conf, HookStage::Pre, HookEvent::new(&info, "")) {
+            log::error!("Run pre-frame hook error! Error:{}", e);
+            alert_dialog("Pre-frame hook failed! ");
+            return Err(e);
+        }
+        // prerendered pictures are finished already, prefetched ones only need the output step
+        let prerendered = prerendered_frame(&conf, &movie_path, cur_frame);
+        let cached = match &prefetcher {
+            Some(p) if prerendered.is_none() => {
+                p.cache().take(&movie_path, cur_frame, &picture_path)
+            }
+            _ => false,
+        };
+        let extracted = match (prerendered, cached) {
+            (Some(path), _) => std::fs::copy(path, &picture_path)
+                .map(|_| ())
+                .map_err(Errors::InnerIOError),
+            (None, true) => finish_frame(&picture_path, &conf, &info),
+            (None, false) => source
+                .extract_frame(cur_frame, &picture_path)
+                .and_then(|_| finish_frame(&picture_path, &conf, &info)),
+        };
+        match extracted {
             Ok(_) => {
                 log::info!("Convert frame to picture ok.");
             }
@@ -89,48 +192,296 @@ pub fn run() -> Result<(), Errors> {
             }
         }
 
-        //4. if all is ok, now we have a picture frame.png . It's time to set it as wallpaper.
-        log::info!("Start to check new frame.png.");
-        let frame_picture = match env::current_dir() {
-            Ok(mut path) => {
-                path.push("frame.png");
-                path
-            }
+        //4. if all is ok, give the picture a name of its own. It's time to set it as wallpaper.
+        let frame_picture = match output.publish(cur_frame) {
+            Ok(path) => path,
             Err(e) => {
-                log::error!("frame.png is not exist! Error:{}", e);
+                log::error!("Publish frame picture error! Error:{}", e);
                 alert_dialog("Get frame picture failed! ");
-                return Err(Errors::InnerIOError(e));
+                return Err(e);
             }
         };
-        match frame_picture.try_exists() {
-            Ok(exist) => {
-                if !exist {
-                    log::error!("frame.png is not exist!");
-                    alert_dialog("Get frame picture failed! ");
-                    return Err(Errors::FramePictureLost);
-                }
-            }
-            Err(e) => {
-                log::error!("frame.png is not exist! Error:{}", e);
-                alert_dialog("Get frame picture failed! ");
-                return Err(Errors::FramePictureLost);
+
+        // several screens get one picture covering the whole desktop, only the wallpaper
+        // shows it, everything else gets the frame itself
+        let mut wallpaper_picture = frame_picture.display().to_string();
+        if conf.is_wallpaper_enabled() {
+            let advance = !conf.is_paused() && conf.get_play_mode() == PlayMode::Interval;
+            match monitors.compose(&mut conf, &wallpaper_picture, advance) {
+                Ok(Some(path)) => wallpaper_picture = path,
+                Ok(None) => {}
+                Err(e) => log::warn!("Lay out frame for monitors error! Error:{}", e),
             }
         }
 
-        log::info!("Start to set frame.png as wallpaper.");
-        match wallpaper::set_from_path(&frame_picture.display().to_string()) {
+        log::info!("Start to set {} as wallpaper.", wallpaper_picture);
+        let mode = conf.get_monitor_mode();
+        if conf.is_wallpaper_enabled() && shown_mode != Some(mode) {
+            let styled = match mode {
+                MonitorMode::Single => restore_wallpaper(&conf),
+                _ => span_wallpaper(&conf),
+            };
+            if let Err(e) = styled {
+                log::warn!("Set wallpaper style error! Error:{}", e);
+            }
+            shown_mode = Some(mode);
+        }
+        let shown = match conf.is_wallpaper_enabled() {
+            true => wallpaper::set_from_path(&wallpaper_picture),
+            // file output profiles only publish the picture
+            false => Ok(()),
+        };
+        match shown {
             Ok(_) => {
+                output.cleanup(&frame_picture);
                 // if set wallpaper ok, update config file
-                conf.set_frame_count(cur_frame);
-                conf.save();
+                conf.set_shown_frame(cur_frame);
+                conf.set_shown_picture(frame_picture.display().to_string());
+                let entry = HistoryEntry {
+                    movie_path: movie_path.clone(),
+                    frame: cur_frame,
+                    time_ms: info.time_ms,
+                    shown_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                    picture_path: frame_picture.display().to_string(),
+                    ..HistoryEntry::default()
+                };
+                if let Err(e) = append_history(&conf, entry) {
+                    log::warn!("Write display history error! Error:{}", e);
+                }
+                if let Some(s) = &server {
+                    s.update(FrameStatus {
+                        movie_path: movie_path.clone(),
+                        frame: cur_frame,
+                        total_frame,
+                        time_ms: info.time_ms,
+                        duration_ms: info.duration_ms,
+                        picture_path: frame_picture.display().to_string(),
+                        shown_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                        paused: conf.is_paused(),
+                        interval_secs: u64::from(conf.get_time_interval()),
+                        next_frame_at: 0,
+                    });
+                }
+                if conf.is_palette_enabled() {
+                    if let Err(e) = update_palette(&conf, &frame_picture) {
+                        log::warn!("Update palette error! Error:{}", e);
+                    }
+                }
+                let event = HookEvent::new(&info, &frame_picture.display().to_string());
+                if let Err(e) = run_frame_hook(&conf, HookStage::Post, event) {
+                    log::error!("Run post-frame hook error! Error:{}", e);
+                    alert_dialog("Post-frame hook failed! ");
+                    return Err(e);
+                }
+                if conf.get_play_mode() == PlayMode::Daily {
+                    conf.set_frame_count(cur_frame);
+                    save_round(&conf, &loaded);
+                } else if conf.is_paused() {
+                    // stay on this frame until resumed
+                    conf.set_frame_count(cur_frame);
+                    save_round(&conf, &loaded);
+                } else {
+                    let step = advance_in(
+                        conf.get_frame_count(),
+                        first_frame,
+                        end_frame,
+                        conf.is_reverse(),
+                        conf.get_end_policy(),
+                    );
+                    conf.set_frame_count(step.next_frame);
+                    conf.set_reverse(step.reverse);
+                    if step.ended {
+                        log::info!(
+                            "Movie reached its last frame. Policy:{}",
+                            conf.get_end_policy()
+                        );
+                        if let Err(e) =
+                            notify_movie_end(&conf.get_end_command(), &conf.get_movie_path())
+                        {
+                            log::warn!("Run movie end command error! Error:{}", e);
+                        }
+                    }
+                    if step.next_movie && !playlist::advance_playlist(&mut conf) {
+                        log::info!("Playlist finished, exit.");
+                        conf.set_exit_flag(true);
+                        if save_round(&conf, &loaded) {
+                            return Ok(());
+                        }
+                    }
+                    if step.stop {
+                        conf.set_exit_flag(true);
+                        if save_round(&conf, &loaded) {
+                            log::info!("End policy is stop, exit.");
+                            return Ok(());
+                        }
+                    }
+                    save_round(&conf, &loaded);
+                }
             }
             Err(e) => {
-                log::error!("Set frame.png as wallper error! Error:{}", e);
+                log::error!("Set frame picture as wallper error! Error:{}", e);
                 alert_dialog("Set frame as wallpaper failed! ");
             }
         };
 
-        //4. thread sleep
-        thread::sleep(Duration::from_secs(u64::from(conf.get_time_interval())));
+        if let Some(p) = &prefetcher {
+            let prerendered = prerendered_frame(&conf, &movie_path, conf.get_frame_count());
+            if conf.get_movie_path() == movie_path && !conf.is_paused() && prerendered.is_none() {
+                p.request(PrefetchJob {
+                    movie_path: movie_path.clone(),
+                    image_pattern: conf.get_image_pattern(),
+                    backend: conf.get_decode_backend(),
+                    frames: upcoming_frames(
+                        &conf,
+                        first_frame,
+                        end_frame,
+                        total_frame,
+                        conf.get_prefetch_frames() as usize,
+                    ),
+                });
+            }
+        }
+
+        //4. thread sleep, animations keep their own frame timing
+        let interval = u64::from(conf.get_time_interval()) as f64;
+        let sleep = interval * source.relative_duration(cur_frame);
+        if let Some(s) = &server {
+            let due = chrono::Local::now() + chrono::Duration::seconds(sleep.max(1.0) as i64);
+            s.set_next_frame_at(due.timestamp());
+        }
+        wait_for_commands(&conf, sleep.max(1.0));
+        if source.keep_open() {
+            opened = Some((movie_path, source));
+        }
+    }
+}
+
+fn config_text(conf: &Config) -> String {
+    serde_json::to_string(conf).unwrap_or_default()
+}
+
+// True when the config file no longer holds what the round loaded: the settings window,
+// which runs next to the loop, saved it.
+fn changed_on_disk(loaded: &str) -> bool {
+    config_text(&config::load()) != loaded
+}
+
+// Save the round's config unless the settings window saved its own meanwhile. Those
+// settings win, the Reload the window sends shows the frame again with them.
+fn save_round(conf: &Config, loaded: &str) -> bool {
+    if changed_on_disk(loaded) {
+        log::info!("Config was changed during the round, keep the new settings.");
+        return false;
+    }
+    conf.save();
+    true
+}
+
+// Sleep for `secs`, waking early when a control command comes in.
+fn wait_for_commands(conf: &Config, secs: f64) {
+    let deadline = Instant::now() + Duration::from_secs_f64(secs);
+    loop {
+        let now = Instant::now();
+        if now >= deadline || has_commands(conf) {
+            return;
+        }
+        thread::sleep((deadline - now).min(Duration::from_secs(1)));
+    }
+}
+
+// Open the settings window next to the running loop, unless it is open already.
+// Closing it wakes the loop so the changes show at once.
+fn show_settings(settings: &mut Option<thread::JoinHandle<()>>) {
+    if matches!(settings, Some(handle) if !handle.is_finished()) {
+        return;
+    }
+    *settings = Some(thread::spawn(|| {
+        if let Err(e) = crate::run_gui::run() {
+            log::error!("Run settings window error! Error:{}", e);
+        }
+        if let Err(e) = push_command(&config::load(), ControlCommand::Reload) {
+            log::warn!("Queue reload command error! Error:{}", e);
+        }
+    }));
+}
+
+// Start or stop the prefetch worker to follow the config.
+fn update_prefetcher(prefetcher: &mut Option<Prefetcher>, conf: &Config) {
+    if conf.get_prefetch_frames() == 0 {
+        *prefetcher = None;
+        return;
+    }
+    if prefetcher.is_some() {
+        return;
+    }
+    let cache = FrameCache::new(
+        conf.state_file("frame_cache"),
+        conf.get_prefetch_cache_mb() * 1024 * 1024,
+        Duration::from_secs(conf.get_prefetch_max_age_hours() * 60 * 60),
+    );
+    *prefetcher = match cache {
+        Ok(c) => Some(Prefetcher::start(c)),
+        Err(e) => {
+            // frames are still extracted on every tick
+            log::error!("Create frame cache error! Error:{}", e);
+            None
+        }
+    };
+}
+
+// Start, restart or stop the HTTP server to follow the config.
+fn update_server(server: &mut Option<FrameServer>, conf: &Config) {
+    if !conf.is_http_enabled() {
+        *server = None;
+        return;
+    }
+    if let Some(s) = server {
+        if s.address() == conf.get_http_address() {
+            return;
+        }
     }
+    // the old server has to let go of its port first
+    *server = None;
+    *server = match FrameServer::start(conf) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            log::error!("Start HTTP server error! Error:{}", e);
+            None
+        }
+    };
+}
+
+// Start, restart or stop the folder watcher to follow the config.
+fn update_watcher(watcher: &mut Option<FolderWatcher>, conf: &Config) {
+    let folder = conf.get_playlist_folder();
+    if !conf.is_watch_folder() || folder.is_empty() {
+        *watcher = None;
+        return;
+    }
+    if let Some(w) = watcher {
+        if w.folder() == folder {
+            return;
+        }
+    }
+    let ffprobe = match ffprobe_exe() {
+        Ok(path) => path,
+        Err(e) => {
+            log::error!("ffprobe is not exist! Error:{}", e);
+            return;
+        }
+    };
+    *watcher = match FolderWatcher::start(
+        &folder,
+        conf.is_playlist_recursive(),
+        &conf.get_playlist_extensions(),
+        conf.get_watch_settle_secs(),
+        &ffprobe,
+    ) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            // fall back to rescanning the folder every tick
+            log::error!("Watch folder {} error! Error:{}", folder, e);
+            None
+        }
+    };
 }
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use super::config::Config;
+use super::control::ControlCommand;
+use super::errors::*;
+use super::overlay::format_timecode;
+use super::source::open_source;
+
+/// A named place in a movie to come back to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Bookmark {
+    pub name: String,
+    pub movie_path: String,
+    pub frame: u64,
+    pub time_ms: u64,
+    pub note: String,
+}
+
+impl Bookmark {
+    pub fn jump_command(&self) -> ControlCommand {
+        ControlCommand::Jump {
+            movie_path: self.movie_path.clone(),
+            frame: self.frame,
+        }
+    }
+}
+
+impl fmt::Display for Bookmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, format_timecode(self.time_ms))
+    }
+}
+
+fn read_bookmarks(path: &Path) -> Result<Vec<Bookmark>, Errors> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| Errors::BookmarkError(e.to_string()))
+}
+
+fn write_bookmarks(path: &Path, bookmarks: &[Bookmark]) -> Result<(), Errors> {
+    let text = serde_json::to_string_pretty(bookmarks)
+        .map_err(|e| Errors::BookmarkError(e.to_string()))?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Bookmarks saved next to the config, empty when there are none yet.
+pub fn load_bookmarks(conf: &Config) -> Result<Vec<Bookmark>, Errors> {
+    let path = conf.state_file("bookmarks.json");
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    read_bookmarks(&path)
+}
+
+pub fn save_bookmarks(conf: &Config, bookmarks: &[Bookmark]) -> Result<(), Errors> {
+    write_bookmarks(&conf.state_file("bookmarks.json"), bookmarks)
+}
+
+/// Bookmark the frame on the desktop right now.
+pub fn bookmark_here(conf: &Config, name: &str, note: &str) -> Result<Bookmark, Errors> {
+    let movie_path = conf.get_movie_path();
+    let frame = conf.get_shown_frame();
+    let mut source = open_source(
+        &movie_path,
+        &conf.get_image_pattern(),
+        conf.get_decode_backend(),
+    )?;
+    Ok(Bookmark {
+        name: name.to_owned(),
+        movie_path,
+        frame,
+        time_ms: source.frame_time_ms(frame),
+        note: note.to_owned(),
+    })
+}
+
+/// Add a bookmark, replacing one with the same name.
+pub fn add_bookmark(bookmarks: &mut Vec<Bookmark>, bookmark: Bookmark) {
+    match bookmarks.iter_mut().find(|b| b.name == bookmark.name) {
+        Some(old) => *old = bookmark,
+        None => bookmarks.push(bookmark),
+    }
+}
+
+pub fn find_bookmark<'a>(bookmarks: &'a [Bookmark], name: &str) -> Option<&'a Bookmark> {
+    bookmarks.iter().find(|b| b.name == name)
+}
+
+pub fn export_bookmarks(bookmarks: &[Bookmark], path: &str) -> Result<(), Errors> {
+    write_bookmarks(Path::new(path), bookmarks)
+}
+
+/// Merge bookmarks from an exported file, imported ones win on equal names.
+/// Returns how many were read.
+pub fn import_bookmarks(bookmarks: &mut Vec<Bookmark>, path: &str) -> Result<usize, Errors> {
+    let imported = read_bookmarks(Path::new(path))?;
+    let count = imported.len();
+    for bookmark in imported {
+        add_bookmark(bookmarks, bookmark);
+    }
+    Ok(count)
+}
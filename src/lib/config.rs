@@ -1,6 +1,16 @@
 use serde::{Deserialize, Serialize};
+
+use super::eink::DeviceProfile;
+use super::hooks::HookFailure;
+use super::monitor::{Monitor, MonitorMode};
+use super::overlay::{ProgressBarStyle, TextOverlay, TextStyle};
+use super::playback::EndPolicy;
+use super::playlist::PlaylistItem;
+use super::profile::profile_dir;
+use super::source::DecodeBackend;
 use std::env;
 use std::fs;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Timetype {
@@ -32,7 +42,30 @@ impl std::fmt::Display for Timetype {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayMode {
+    #[default]
+    Interval,
+    Daily,
+}
+impl PlayMode {
+    pub const ALL: [PlayMode; 2] = [PlayMode::Interval, PlayMode::Daily];
+}
+impl std::fmt::Display for PlayMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PlayMode::Interval => "Interval",
+                PlayMode::Daily => "Daily",
+            }
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
     config_path: String,
     movie_path: String,
@@ -41,6 +74,54 @@ pub struct Config {
     frame_time_type: i32,
     frame_count: u64,
     exit_flag: i32,
+    play_mode: i32,
+    day_start: String,
+    day_end: String,
+    end_policy: i32,
+    play_reverse: i32,
+    end_command: String,
+    playlist: Vec<PlaylistItem>,
+    playlist_index: usize,
+    playlist_folder: String,
+    playlist_recursive: i32,
+    playlist_extensions: String,
+    shuffle: i32,
+    repeat: i32,
+    shuffle_played: Vec<String>,
+    watch_folder: i32,
+    watch_settle_secs: u64,
+    image_pattern: String,
+    output_width: u32,
+    output_height: u32,
+    decode_backend: i32,
+    subtitle_enabled: i32,
+    subtitle_path: String,
+    subtitle_stream: i32,
+    subtitle_style: TextStyle,
+    text_overlays: Vec<TextOverlay>,
+    progress_bar: ProgressBarStyle,
+    shown_frame: u64,
+    paused: i32,
+    prefetch_frames: u32,
+    prefetch_cache_mb: u64,
+    prefetch_max_age_hours: u64,
+    frame_history: u32,
+    shown_picture: String,
+    history_limit: u32,
+    palette_enabled: i32,
+    palette_hook: String,
+    pre_frame_hook: String,
+    post_frame_hook: String,
+    hook_timeout_secs: u64,
+    hook_failure: i32,
+    http_enabled: i32,
+    http_address: String,
+    eink_devices: Vec<DeviceProfile>,
+    monitor_mode: i32,
+    monitors: Vec<Monitor>,
+    monitor_bezel: [u32; 2],
+    wallpaper_enabled: i32,
+    output_dir: String,
 }
 
 impl Config {
@@ -49,6 +130,12 @@ impl Config {
 
         let conf_file = std::path::PathBuf::from(conf.config_path.clone());
         if !conf_file.is_file() {
+            // a new profile starts with an empty folder of its own
+            if let Some(dir) = conf_file.parent() {
+                if let Err(why) = fs::create_dir_all(dir) {
+                    log::error!("Create profile folder failed! Error Reason:{}", why);
+                }
+            }
             save_config(&conf);
         }
 
@@ -96,6 +183,9 @@ impl Config {
 
     pub fn set_frame_count(&mut self, count: u64) {
         self.frame_count = count;
+        if let Some(item) = self.playlist.get_mut(self.playlist_index) {
+            item.progress = count;
+        }
     }
 
     pub fn get_frame_count(&self) -> u64 {
@@ -116,6 +206,473 @@ impl Config {
     pub fn should_exit(&self) -> bool {
         !matches!(self.exit_flag, 0)
     }
+
+    pub fn set_play_mode(&mut self, mode: PlayMode) {
+        self.play_mode = match mode {
+            PlayMode::Interval => 0,
+            PlayMode::Daily => 1,
+        };
+    }
+    pub fn get_play_mode(&self) -> PlayMode {
+        match self.play_mode {
+            1 => PlayMode::Daily,
+            _ => PlayMode::Interval,
+        }
+    }
+
+    /// Daily window as local wall-clock times in "HH:MM" form.
+    /// When end is not after start the window runs past midnight, equal values mean a full day.
+    pub fn set_day_window(&mut self, start: String, end: String) {
+        self.day_start = start;
+        self.day_end = end;
+    }
+    pub fn get_day_start(&self) -> String {
+        self.day_start.clone()
+    }
+    pub fn get_day_end(&self) -> String {
+        self.day_end.clone()
+    }
+
+    pub fn set_end_policy(&mut self, policy: EndPolicy) {
+        self.end_policy = match policy {
+            EndPolicy::Loop => 0,
+            EndPolicy::Hold => 1,
+            EndPolicy::Stop => 2,
+            EndPolicy::PingPong => 3,
+            EndPolicy::NextMovie => 4,
+        };
+    }
+    pub fn get_end_policy(&self) -> EndPolicy {
+        match self.end_policy {
+            1 => EndPolicy::Hold,
+            2 => EndPolicy::Stop,
+            3 => EndPolicy::PingPong,
+            4 => EndPolicy::NextMovie,
+            _ => EndPolicy::Loop,
+        }
+    }
+
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.play_reverse = match reverse {
+            true => 1,
+            _ => 0,
+        }
+    }
+    pub fn is_reverse(&self) -> bool {
+        !matches!(self.play_reverse, 0)
+    }
+
+    /// Command line run once each time the movie reaches its last frame.
+    pub fn set_end_command(&mut self, command: String) {
+        self.end_command = command;
+    }
+    pub fn get_end_command(&self) -> String {
+        self.end_command.clone()
+    }
+
+    pub fn set_playlist(&mut self, playlist: Vec<PlaylistItem>) {
+        self.playlist = playlist;
+    }
+    pub fn get_playlist(&self) -> Vec<PlaylistItem> {
+        self.playlist.clone()
+    }
+
+    pub fn set_playlist_index(&mut self, index: usize) {
+        self.playlist_index = index;
+    }
+    pub fn get_playlist_index(&self) -> usize {
+        self.playlist_index
+    }
+
+    pub fn current_playlist_item(&self) -> Option<PlaylistItem> {
+        self.playlist.get(self.playlist_index).cloned()
+    }
+
+    /// Switch to a playlist entry, resuming it where it was left.
+    pub fn select_playlist_item(&mut self, index: usize) {
+        let index = if index < self.playlist.len() {
+            index
+        } else {
+            0
+        };
+        let item = match self.playlist.get(index) {
+            Some(item) => item.clone(),
+            None => return,
+        };
+        self.playlist_index = index;
+        self.movie_path = item.path;
+        self.frame_count = item.progress.max(item.start_frame);
+    }
+
+    /// Show `frame` of a movie next. With a playlist in use the movie joins it when missing.
+    pub fn jump_to(&mut self, path: String, frame: u64) {
+        if !self.playlist.is_empty() {
+            let index = match self.playlist.iter().position(|item| item.path == path) {
+                Some(i) => i,
+                None => {
+                    self.playlist.push(PlaylistItem::new(path.clone()));
+                    self.playlist.len() - 1
+                }
+            };
+            self.select_playlist_item(index);
+        }
+        self.movie_path = path;
+        self.set_frame_count(frame);
+        self.play_reverse = 0;
+    }
+
+    /// Folder whose movies make up the playlist, empty to use the saved list.
+    pub fn set_playlist_folder(&mut self, folder: String, recursive: bool, extensions: String) {
+        self.playlist_folder = folder;
+        self.playlist_recursive = match recursive {
+            true => 1,
+            _ => 0,
+        };
+        self.playlist_extensions = extensions;
+    }
+    pub fn get_playlist_folder(&self) -> String {
+        self.playlist_folder.clone()
+    }
+    pub fn is_playlist_recursive(&self) -> bool {
+        !matches!(self.playlist_recursive, 0)
+    }
+    pub fn get_playlist_extensions(&self) -> String {
+        self.playlist_extensions.clone()
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = match shuffle {
+            true => 1,
+            _ => 0,
+        }
+    }
+    pub fn is_shuffle(&self) -> bool {
+        !matches!(self.shuffle, 0)
+    }
+
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.repeat = match repeat {
+            true => 1,
+            _ => 0,
+        }
+    }
+    pub fn is_repeat(&self) -> bool {
+        !matches!(self.repeat, 0)
+    }
+
+    /// Movies shuffle has already played in this round through the playlist.
+    pub fn set_shuffle_played(&mut self, played: Vec<String>) {
+        self.shuffle_played = played;
+    }
+    pub fn get_shuffle_played(&self) -> Vec<String> {
+        self.shuffle_played.clone()
+    }
+
+    /// Watch the playlist folder instead of rescanning it on every tick.
+    pub fn set_watch_folder(&mut self, watch: bool) {
+        self.watch_folder = match watch {
+            true => 1,
+            _ => 0,
+        }
+    }
+    pub fn is_watch_folder(&self) -> bool {
+        !matches!(self.watch_folder, 0)
+    }
+
+    /// Seconds a new file's size must stay the same before it joins the playlist.
+    pub fn set_watch_settle_secs(&mut self, secs: u64) {
+        self.watch_settle_secs = secs;
+    }
+    pub fn get_watch_settle_secs(&self) -> u64 {
+        self.watch_settle_secs
+    }
+
+    /// File name glob picking the frames when the movie is a folder of pictures.
+    pub fn set_image_pattern(&mut self, pattern: String) {
+        self.image_pattern = pattern;
+    }
+    pub fn get_image_pattern(&self) -> String {
+        self.image_pattern.clone()
+    }
+
+    /// Size of the picture handed to the desktop, 0 keeps the source size.
+    pub fn set_output_size(&mut self, width: u32, height: u32) {
+        self.output_width = width;
+        self.output_height = height;
+    }
+    pub fn get_output_width(&self) -> u32 {
+        self.output_width
+    }
+    pub fn get_output_height(&self) -> u32 {
+        self.output_height
+    }
+
+    pub fn set_decode_backend(&mut self, backend: DecodeBackend) {
+        self.decode_backend = match backend {
+            DecodeBackend::Cli => 0,
+            DecodeBackend::Libav => 1,
+        };
+    }
+    pub fn get_decode_backend(&self) -> DecodeBackend {
+        match self.decode_backend {
+            1 => DecodeBackend::Libav,
+            _ => DecodeBackend::Cli,
+        }
+    }
+
+    pub fn set_subtitle_enabled(&mut self, enabled: bool) {
+        self.subtitle_enabled = match enabled {
+            true => 1,
+            _ => 0,
+        }
+    }
+    pub fn is_subtitle_enabled(&self) -> bool {
+        !matches!(self.subtitle_enabled, 0)
+    }
+
+    /// Subtitle file to use, empty looks for a .srt/.ass next to the movie.
+    pub fn set_subtitle_path(&mut self, path: String) {
+        self.subtitle_path = path;
+    }
+    pub fn get_subtitle_path(&self) -> String {
+        self.subtitle_path.clone()
+    }
+
+    /// Index of the embedded subtitle stream, -1 to not use embedded subtitles.
+    pub fn set_subtitle_stream(&mut self, stream: i32) {
+        self.subtitle_stream = stream;
+    }
+    pub fn get_subtitle_stream(&self) -> i32 {
+        self.subtitle_stream
+    }
+
+    pub fn set_subtitle_style(&mut self, style: TextStyle) {
+        self.subtitle_style = style;
+    }
+    pub fn get_subtitle_style(&self) -> TextStyle {
+        self.subtitle_style.clone()
+    }
+
+    pub fn set_text_overlays(&mut self, overlays: Vec<TextOverlay>) {
+        self.text_overlays = overlays;
+    }
+    pub fn get_text_overlays(&self) -> Vec<TextOverlay> {
+        self.text_overlays.clone()
+    }
+
+    pub fn set_progress_bar(&mut self, style: ProgressBarStyle) {
+        self.progress_bar = style;
+    }
+    pub fn get_progress_bar(&self) -> ProgressBarStyle {
+        self.progress_bar.clone()
+    }
+
+    pub fn get_config_path(&self) -> String {
+        self.config_path.clone()
+    }
+
+    /// A file kept next to the config, like the control queue or bookmarks.
+    pub fn state_file(&self, name: &str) -> PathBuf {
+        let mut path = PathBuf::from(&self.config_path);
+        path.set_file_name(name);
+        path
+    }
+
+    /// Frame on the desktop right now, frame_count is the one coming next.
+    pub fn set_shown_frame(&mut self, frame: u64) {
+        self.shown_frame = frame;
+    }
+    pub fn get_shown_frame(&self) -> u64 {
+        self.shown_frame
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        match paused {
+            true => self.paused = 1,
+            false => self.paused = 0,
+        }
+    }
+    pub fn is_paused(&self) -> bool {
+        !matches!(self.paused, 0)
+    }
+
+    /// How many upcoming frames are extracted ahead of time, 0 turns the cache off.
+    pub fn set_prefetch_frames(&mut self, frames: u32) {
+        self.prefetch_frames = frames;
+    }
+    pub fn get_prefetch_frames(&self) -> u32 {
+        self.prefetch_frames
+    }
+
+    pub fn set_prefetch_limits(&mut self, cache_mb: u64, max_age_hours: u64) {
+        self.prefetch_cache_mb = cache_mb;
+        self.prefetch_max_age_hours = max_age_hours;
+    }
+    pub fn get_prefetch_cache_mb(&self) -> u64 {
+        self.prefetch_cache_mb
+    }
+    pub fn get_prefetch_max_age_hours(&self) -> u64 {
+        self.prefetch_max_age_hours
+    }
+
+    /// Earlier frame pictures kept on disk besides the one on the desktop.
+    pub fn set_frame_history(&mut self, count: u32) {
+        self.frame_history = count;
+    }
+    pub fn get_frame_history(&self) -> u32 {
+        self.frame_history
+    }
+
+    /// Picture file of the frame on the desktop.
+    pub fn set_shown_picture(&mut self, path: String) {
+        self.shown_picture = path;
+    }
+    pub fn get_shown_picture(&self) -> String {
+        self.shown_picture.clone()
+    }
+
+    /// Entries in the display history before it rotates.
+    pub fn set_history_limit(&mut self, limit: u32) {
+        self.history_limit = limit.max(1);
+    }
+    pub fn get_history_limit(&self) -> u32 {
+        self.history_limit.max(1)
+    }
+
+    pub fn set_palette_enabled(&mut self, enabled: bool) {
+        match enabled {
+            true => self.palette_enabled = 1,
+            false => self.palette_enabled = 0,
+        }
+    }
+    pub fn is_palette_enabled(&self) -> bool {
+        !matches!(self.palette_enabled, 0)
+    }
+
+    /// Command line run after each new palette, so themes can follow the film.
+    pub fn set_palette_hook(&mut self, command: String) {
+        self.palette_hook = command;
+    }
+    pub fn get_palette_hook(&self) -> String {
+        self.palette_hook.clone()
+    }
+
+    /// Command line run before each frame is extracted.
+    pub fn set_pre_frame_hook(&mut self, command: String) {
+        self.pre_frame_hook = command;
+    }
+    pub fn get_pre_frame_hook(&self) -> String {
+        self.pre_frame_hook.clone()
+    }
+
+    /// Command line run after each wallpaper was set.
+    pub fn set_post_frame_hook(&mut self, command: String) {
+        self.post_frame_hook = command;
+    }
+    pub fn get_post_frame_hook(&self) -> String {
+        self.post_frame_hook.clone()
+    }
+
+    pub fn set_hook_timeout_secs(&mut self, secs: u64) {
+        self.hook_timeout_secs = secs.max(1);
+    }
+    pub fn get_hook_timeout_secs(&self) -> u64 {
+        self.hook_timeout_secs.max(1)
+    }
+
+    pub fn set_hook_failure(&mut self, policy: HookFailure) {
+        self.hook_failure = match policy {
+            HookFailure::Ignore => 0,
+            HookFailure::Warn => 1,
+            HookFailure::Abort => 2,
+        }
+    }
+    pub fn get_hook_failure(&self) -> HookFailure {
+        match self.hook_failure {
+            0 => HookFailure::Ignore,
+            2 => HookFailure::Abort,
+            _ => HookFailure::Warn,
+        }
+    }
+
+    pub fn set_http_enabled(&mut self, enabled: bool) {
+        match enabled {
+            true => self.http_enabled = 1,
+            false => self.http_enabled = 0,
+        }
+    }
+    pub fn is_http_enabled(&self) -> bool {
+        !matches!(self.http_enabled, 0)
+    }
+
+    /// Address the HTTP server listens on. Only this machine can reach the default one.
+    pub fn set_http_address(&mut self, address: String) {
+        self.http_address = address;
+    }
+    pub fn get_http_address(&self) -> String {
+        self.http_address.clone()
+    }
+
+    /// E-ink frames served by the HTTP server.
+    pub fn set_eink_devices(&mut self, devices: Vec<DeviceProfile>) {
+        self.eink_devices = devices;
+    }
+    pub fn get_eink_devices(&self) -> Vec<DeviceProfile> {
+        self.eink_devices.clone()
+    }
+
+    pub fn set_monitor_mode(&mut self, mode: MonitorMode) {
+        self.monitor_mode = match mode {
+            MonitorMode::Single => 0,
+            MonitorMode::Span => 1,
+            MonitorMode::PerMonitor => 2,
+        }
+    }
+    pub fn get_monitor_mode(&self) -> MonitorMode {
+        match self.monitor_mode {
+            1 => MonitorMode::Span,
+            2 => MonitorMode::PerMonitor,
+            _ => MonitorMode::Single,
+        }
+    }
+
+    /// Declared screens, empty detects them.
+    pub fn set_monitors(&mut self, monitors: Vec<Monitor>) {
+        self.monitors = monitors;
+    }
+    pub fn get_monitors(&self) -> Vec<Monitor> {
+        self.monitors.clone()
+    }
+
+    /// Pixels hidden between neighbouring screens in span mode, [horizontal, vertical].
+    pub fn set_monitor_bezel(&mut self, bezel: [u32; 2]) {
+        self.monitor_bezel = bezel;
+    }
+    pub fn get_monitor_bezel(&self) -> [u32; 2] {
+        self.monitor_bezel
+    }
+
+    /// Off for profiles that only feed frames to files or the HTTP server.
+    pub fn set_wallpaper_enabled(&mut self, enabled: bool) {
+        match enabled {
+            true => self.wallpaper_enabled = 1,
+            false => self.wallpaper_enabled = 0,
+        }
+    }
+    pub fn is_wallpaper_enabled(&self) -> bool {
+        !matches!(self.wallpaper_enabled, 0)
+    }
+
+    /// Folder the finished frames are published to, empty keeps them next to the config.
+    pub fn set_output_dir(&mut self, dir: String) {
+        self.output_dir = dir;
+    }
+    pub fn get_output_dir(&self) -> PathBuf {
+        match self.output_dir.trim().is_empty() {
+            true => self.state_file("frames"),
+            false => PathBuf::from(self.output_dir.trim()),
+        }
+    }
 }
 
 impl Default for Config {
@@ -123,7 +680,7 @@ impl Default for Config {
         let mut movie_path = env::current_dir().unwrap();
         movie_path.push("Ayanami_Rei.mp4");
 
-        let mut config_file_path = env::current_dir().unwrap();
+        let mut config_file_path = profile_dir();
         config_file_path.push("config.json");
 
         let conf = Config {
@@ -132,8 +689,56 @@ impl Default for Config {
             time_interval: ((60 * 60) / 24) as u32,
             time_type: 1,
             frame_time_type: 1,
-            frame_count: 1,
+            frame_count: 0,
             exit_flag: 0,
+            play_mode: 0,
+            day_start: String::from("00:00"),
+            day_end: String::from("00:00"),
+            end_policy: 0,
+            play_reverse: 0,
+            end_command: String::new(),
+            playlist: Vec::new(),
+            playlist_index: 0,
+            playlist_folder: String::new(),
+            playlist_recursive: 0,
+            playlist_extensions: String::from("mp4,mkv,avi,mov,webm,gif,webp,apng"),
+            shuffle: 0,
+            repeat: 1,
+            shuffle_played: Vec::new(),
+            watch_folder: 0,
+            watch_settle_secs: 5,
+            image_pattern: String::new(),
+            output_width: 0,
+            output_height: 0,
+            decode_backend: 0,
+            subtitle_enabled: 0,
+            subtitle_path: String::new(),
+            subtitle_stream: -1,
+            subtitle_style: TextStyle::default(),
+            text_overlays: Vec::new(),
+            progress_bar: ProgressBarStyle::default(),
+            shown_frame: 0,
+            paused: 0,
+            prefetch_frames: 3,
+            prefetch_cache_mb: 256,
+            prefetch_max_age_hours: 24,
+            frame_history: 0,
+            shown_picture: String::new(),
+            history_limit: 1000,
+            palette_enabled: 0,
+            palette_hook: String::new(),
+            pre_frame_hook: String::new(),
+            post_frame_hook: String::new(),
+            hook_timeout_secs: 10,
+            hook_failure: 1,
+            http_enabled: 0,
+            http_address: String::from("127.0.0.1:8642"),
+            eink_devices: Vec::new(),
+            monitor_mode: 0,
+            monitors: Vec::new(),
+            monitor_bezel: [0, 0],
+            wallpaper_enabled: 1,
+            output_dir: String::new(),
         };
 
         conf
@@ -145,8 +750,12 @@ pub fn load() -> Config {
     let conf_file = std::path::PathBuf::from(conf.config_path.clone());
     if conf_file.is_file() {
         let settings = fs::read_to_string(conf_file).unwrap();
-        conf = match serde_json::from_str(settings.as_str()) {
-            Ok(cont) => cont,
+        conf = match serde_json::from_str::<Config>(settings.as_str()) {
+            // the file belongs to the active profile wherever it was copied from
+            Ok(cont) => Config {
+                config_path: conf.config_path,
+                ..cont
+            },
             Err(why) => {
                 log::error!(
                     "Serde_json convert config from string failed! Error Reason:{}",
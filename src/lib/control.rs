@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::PathBuf;
+
+use super::config::{Config, PlayMode};
+use super::errors::*;
+use super::profile::lock_name;
+
+/// Requests for the running wallpaper loop, queued in control.json next to the config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    Next,
+    Prev,
+    Pause,
+    Resume,
+    Seek(u64),
+    NextChapter,
+    PrevChapter,
+    /// Chapter by its index from 0. `goto-chapter` counts from 1, as the settings window does.
+    GotoChapter(usize),
+    /// Show a frame of any movie, used by bookmarks.
+    Jump {
+        movie_path: String,
+        frame: u64,
+    },
+    /// Play another movie from its start, sent when a second copy is started with a file.
+    Open {
+        movie_path: String,
+    },
+    /// Open the settings window of the running instance.
+    ShowSettings,
+    /// The config was changed, show the frame again with it.
+    Reload,
+}
+
+impl ControlCommand {
+    /// Read a command from command-line words like `seek 1200` or `goto-chapter 3`.
+    pub fn parse(name: &str, arg: Option<&str>) -> Option<ControlCommand> {
+        let number = arg.and_then(|a| a.trim().parse::<u64>().ok());
+        match name {
+            "next" => Some(ControlCommand::Next),
+            "prev" => Some(ControlCommand::Prev),
+            "pause" => Some(ControlCommand::Pause),
+            "resume" => Some(ControlCommand::Resume),
+            "seek" => number.map(ControlCommand::Seek),
+            "next-chapter" => Some(ControlCommand::NextChapter),
+            "prev-chapter" => Some(ControlCommand::PrevChapter),
+            "goto-chapter" => number
+                .and_then(|n| (n as usize).checked_sub(1))
+                .map(ControlCommand::GotoChapter),
+            _ => None,
+        }
+    }
+}
+
+fn control_path(conf: &Config) -> PathBuf {
+    conf.state_file("control.json")
+}
+
+fn read_queue(path: &PathBuf) -> Vec<ControlCommand> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// The loop, the settings window, the HTTP server and the command line all change the
+// queue. Each holds this lock from reading the queue to writing it back, so no command is
+// lost in between.
+fn lock_queue() -> Result<named_lock::NamedLock, Errors> {
+    Ok(named_lock::NamedLock::create(&format!(
+        "{}-control",
+        lock_name()
+    ))?)
+}
+
+fn write_queue(path: &PathBuf, queue: &[ControlCommand]) -> Result<(), Errors> {
+    match serde_json::to_string(queue) {
+        Ok(text) => fs::write(path, text)?,
+        Err(e) => return Err(Errors::ControlError(e.to_string())),
+    }
+    Ok(())
+}
+
+pub fn push_command(conf: &Config, command: ControlCommand) -> Result<(), Errors> {
+    let lock = lock_queue()?;
+    let _guard = lock.lock()?;
+    let path = control_path(conf);
+    let mut queue = read_queue(&path);
+    queue.push(command);
+    write_queue(&path, &queue)
+}
+
+pub fn has_commands(conf: &Config) -> bool {
+    !read_queue(&control_path(conf)).is_empty()
+}
+
+/// Drop queued commands matching `stale`, keeping the rest in order.
+pub fn discard_commands(conf: &Config, stale: impl Fn(&ControlCommand) -> bool) {
+    let discarded = lock_queue().and_then(|lock| {
+        let _guard = lock.lock()?;
+        let path = control_path(conf);
+        let mut queue = read_queue(&path);
+        let count = queue.len();
+        queue.retain(|c| !stale(c));
+        match queue.len() == count {
+            true => Ok(()),
+            false => write_queue(&path, &queue),
+        }
+    });
+    if let Err(e) = discarded {
+        log::warn!("Discard control commands error! Error:{}", e);
+    }
+}
+
+/// Take every queued command, leaving the queue empty.
+pub fn take_commands(conf: &Config) -> Vec<ControlCommand> {
+    let taken = lock_queue().and_then(|lock| {
+        let _guard = lock.lock()?;
+        let path = control_path(conf);
+        let queue = read_queue(&path);
+        if !queue.is_empty() {
+            fs::remove_file(&path)?;
+        }
+        Ok(queue)
+    });
+    match taken {
+        Ok(queue) => queue,
+        Err(e) => {
+            log::warn!("Take control commands error! Error:{}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Frame each chapter starts on, for a movie of `total_frame` frames lasting `duration_ms`.
+pub fn chapter_frames(chapter_starts_ms: &[u64], total_frame: u64, duration_ms: u64) -> Vec<u64> {
+    if duration_ms == 0 {
+        return Vec::new();
+    }
+    chapter_starts_ms
+        .iter()
+        .map(|ms| (*ms as u128 * total_frame as u128 / duration_ms as u128) as u64)
+        .map(|f| f.min(total_frame.saturating_sub(1)))
+        .collect()
+}
+
+// Going back from just after a chapter start skips to the chapter before, like a player does.
+const PREV_CHAPTER_GRACE_MS: u64 = 3000;
+
+/// Apply one command to the playback state. Returns true when a new frame should be shown now.
+/// `chapters` are the chapter frames of a movie of `total_frame` frames lasting `duration_ms`.
+pub fn apply_command(
+    conf: &mut Config,
+    command: &ControlCommand,
+    total_frame: u64,
+    duration_ms: u64,
+    chapters: &[u64],
+) -> bool {
+    let last_frame = total_frame.saturating_sub(1);
+    let grace = match duration_ms {
+        0 => 0,
+        ms => (PREV_CHAPTER_GRACE_MS as u128 * total_frame as u128 / ms as u128) as u64,
+    };
+    let shown = conf.get_shown_frame();
+    let target = match command {
+        ControlCommand::Pause => {
+            conf.set_paused(true);
+            return false;
+        }
+        ControlCommand::Resume => {
+            conf.set_paused(false);
+            return true;
+        }
+        ControlCommand::Open { movie_path } => {
+            if *movie_path != conf.get_movie_path() {
+                conf.jump_to(movie_path.clone(), 0);
+            }
+            return true;
+        }
+        // the loop opens the window itself
+        ControlCommand::ShowSettings => return false,
+        ControlCommand::Reload => return true,
+        // the clock picks the frame in daily mode
+        _ if conf.get_play_mode() == PlayMode::Daily => {
+            log::info!("Ignore {:?} in daily mode.", command);
+            return false;
+        }
+        ControlCommand::Next => shown + 1,
+        ControlCommand::Prev => shown.saturating_sub(1),
+        ControlCommand::Seek(frame) => *frame,
+        ControlCommand::NextChapter => match chapters.iter().find(|c| **c > shown) {
+            Some(c) => *c,
+            None => return false,
+        },
+        ControlCommand::PrevChapter => match chapters.iter().rev().find(|c| **c + grace < shown) {
+            Some(c) => *c,
+            None => 0,
+        },
+        ControlCommand::Jump { movie_path, frame } => {
+            if *movie_path != conf.get_movie_path() {
+                conf.jump_to(movie_path.clone(), *frame);
+                return true;
+            }
+            *frame
+        }
+        ControlCommand::GotoChapter(index) => match chapters.get(*index) {
+            Some(c) => *c,
+            None => {
+                log::warn!("No chapter {} in this movie.", index + 1);
+                return false;
+            }
+        },
+    };
+    conf.set_frame_count(target.min(last_frame));
+    conf.set_reverse(false);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapter_frames_scale_to_the_movie() {
+        assert_eq!(
+            chapter_frames(&[0, 30_000, 60_000], 2_400, 100_000),
+            vec![0, 720, 1_440]
+        );
+        // a chapter past the end lands on the last frame
+        assert_eq!(chapter_frames(&[120_000], 2_400, 100_000), vec![2_399]);
+        assert!(chapter_frames(&[0, 1_000], 2_400, 0).is_empty());
+    }
+
+    #[test]
+    fn goto_chapter_counts_from_one() {
+        assert_eq!(
+            ControlCommand::parse("goto-chapter", Some("3")),
+            Some(ControlCommand::GotoChapter(2))
+        );
+        assert_eq!(ControlCommand::parse("goto-chapter", Some("0")), None);
+    }
+
+    #[test]
+    fn prev_chapter_grace_follows_the_frame_rate() {
+        // 50 fps, the 3 second grace is 150 frames
+        let chapters = [0, 1_000];
+        let prev = |shown: u64| {
+            let mut conf = Config::default();
+            conf.set_shown_frame(shown);
+            apply_command(
+                &mut conf,
+                &ControlCommand::PrevChapter,
+                5_000,
+                100_000,
+                &chapters,
+            );
+            conf.get_frame_count()
+        };
+        assert_eq!(prev(1_100), 0);
+        assert_eq!(prev(1_200), 1_000);
+    }
+}
@@ -0,0 +1,260 @@
+use image::imageops::FilterType;
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use std::io::Cursor;
+
+use super::errors::*;
+
+/// How palette indices are packed into bytes, rows always start on a new byte.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteFormat {
+    /// Eight pixels per byte, leftmost pixel in the highest bit.
+    #[default]
+    Packed1,
+    /// Four pixels per byte.
+    Packed2,
+    /// Two pixels per byte, as 7-colour panels want it.
+    Packed4,
+    /// One pixel per byte.
+    Byte8,
+}
+impl ByteFormat {
+    pub fn bits(&self) -> u32 {
+        match self {
+            ByteFormat::Packed1 => 1,
+            ByteFormat::Packed2 => 2,
+            ByteFormat::Packed4 => 4,
+            ByteFormat::Byte8 => 8,
+        }
+    }
+}
+
+/// An e-ink frame that polls the HTTP server for pictures made for its panel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DeviceProfile {
+    /// Served under /device/<name>/.
+    pub name: String,
+    /// Panel size in its native orientation.
+    pub width: u32,
+    pub height: u32,
+    /// Clockwise degrees the panel is mounted at, 0, 90, 180 or 270.
+    pub rotation: u32,
+    /// Colours the panel can show, index order is the order of the packed values.
+    pub palette: Vec<[u8; 3]>,
+    pub byte_format: ByteFormat,
+    /// Floyd-Steinberg dithering, nearest colour when off.
+    pub dither: bool,
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        DeviceProfile {
+            name: String::from("eink"),
+            width: 800,
+            height: 480,
+            rotation: 0,
+            palette: vec![[0, 0, 0], [255, 255, 255]],
+            byte_format: ByteFormat::Packed1,
+            dither: true,
+        }
+    }
+}
+
+/// A frame rendered for one device.
+#[derive(Debug, Clone, Default)]
+pub struct PackedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub bits: u32,
+    pub data: Vec<u8>,
+    /// What the panel will look like, as PNG.
+    pub preview: Vec<u8>,
+}
+
+fn nearest(palette: &[[u8; 3]], c: [f32; 3]) -> usize {
+    let distance = |p: &[u8; 3]| (0..3).map(|i| (p[i] as f32 - c[i]).powi(2)).sum::<f32>();
+    (0..palette.len())
+        .min_by(|a, b| distance(&palette[*a]).total_cmp(&distance(&palette[*b])))
+        .unwrap_or(0)
+}
+
+// Map every pixel to a palette index, spreading the error over the neighbours when dithering.
+fn quantize(picture: &RgbImage, palette: &[[u8; 3]], dither: bool) -> Vec<u8> {
+    let (width, height) = (picture.width() as usize, picture.height() as usize);
+    let mut work: Vec<[f32; 3]> = picture
+        .pixels()
+        .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32])
+        .collect();
+    let mut indices = vec![0u8; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let old = work[i];
+            let index = nearest(palette, old);
+            indices[i] = index as u8;
+            if !dither {
+                continue;
+            }
+            let new = palette[index];
+            let error = [
+                old[0] - new[0] as f32,
+                old[1] - new[1] as f32,
+                old[2] - new[2] as f32,
+            ];
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                if nx < 0 || nx >= width as isize || y + dy >= height {
+                    return;
+                }
+                let n = (y + dy) * width + nx as usize;
+                for c in 0..3 {
+                    work[n][c] += error[c] * weight;
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+    indices
+}
+
+fn pack(indices: &[u8], width: u32, bits: u32) -> Vec<u8> {
+    let per_byte = (8 / bits) as usize;
+    let mut data = Vec::new();
+    for row in indices.chunks(width.max(1) as usize) {
+        for group in row.chunks(per_byte) {
+            let mut byte = 0u8;
+            for (i, index) in group.iter().enumerate() {
+                byte |= index << (8 - bits as usize * (i + 1));
+            }
+            data.push(byte);
+        }
+    }
+    data
+}
+
+/// Fit a picture onto the device's panel, letterboxed with the first palette colour,
+/// turned for the mounting and packed in its byte format.
+pub fn render_for_device(
+    picture_path: &str,
+    device: &DeviceProfile,
+) -> Result<PackedFrame, Errors> {
+    let bits = device.byte_format.bits();
+    if device.palette.is_empty() || device.palette.len() > 1 << bits {
+        return Err(Errors::EinkError(format!(
+            "{} has {} colours, {} bits per pixel hold 1 to {}",
+            device.name,
+            device.palette.len(),
+            bits,
+            1 << bits
+        )));
+    }
+    if device.width == 0 || device.height == 0 {
+        return Err(Errors::EinkError(format!("{} has no size", device.name)));
+    }
+    if ![0, 90, 180, 270].contains(&device.rotation) {
+        return Err(Errors::EinkError(format!(
+            "{} is turned {} degrees, use 0, 90, 180 or 270",
+            device.name, device.rotation
+        )));
+    }
+    // the picture is laid out as the viewer sees the panel
+    let (view_width, view_height) = match device.rotation {
+        90 | 270 => (device.height, device.width),
+        _ => (device.width, device.height),
+    };
+    let picture = image::open(picture_path)?
+        .resize(view_width, view_height, FilterType::Lanczos3)
+        .to_rgb8();
+    let mut view = RgbImage::from_pixel(view_width, view_height, Rgb(device.palette[0]));
+    image::imageops::overlay(
+        &mut view,
+        &picture,
+        ((view_width - picture.width()) / 2) as i64,
+        ((view_height - picture.height()) / 2) as i64,
+    );
+    let panel = match device.rotation {
+        90 => image::imageops::rotate270(&view),
+        180 => image::imageops::rotate180(&view),
+        270 => image::imageops::rotate90(&view),
+        _ => view,
+    };
+
+    let indices = quantize(&panel, &device.palette, device.dither);
+    let shown = RgbImage::from_fn(panel.width(), panel.height(), |x, y| {
+        Rgb(device.palette[indices[(y * panel.width() + x) as usize] as usize])
+    });
+    let mut preview = Vec::new();
+    shown.write_to(
+        &mut Cursor::new(&mut preview),
+        image::ImageOutputFormat::Png,
+    )?;
+    Ok(PackedFrame {
+        width: panel.width(),
+        height: panel.height(),
+        bits,
+        data: pack(&indices, panel.width(), bits),
+        preview,
+    })
+}
+
+/// Seconds a device should sleep before polling again: until just after the next frame
+/// is due, or one interval when that is not known, as while paused.
+pub fn next_poll_secs(next_frame_at: i64, interval_secs: u64, now: i64) -> u64 {
+    // give the loop a moment to publish the new frame
+    const MARGIN: i64 = 5;
+    const MIN_POLL: u64 = 10;
+    let secs = match next_frame_at > now {
+        true => (next_frame_at - now + MARGIN) as u64,
+        false => interval_secs,
+    };
+    secs.max(MIN_POLL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_starts_rows_on_a_new_byte() {
+        assert_eq!(
+            pack(&[1, 0, 1, 1, 0, 0, 0, 1, 1], 9, 1),
+            vec![0b1011_0001, 0b1000_0000]
+        );
+        assert_eq!(
+            pack(&[1, 0, 1, 1, 0, 1], 3, 1),
+            vec![0b1010_0000, 0b1010_0000]
+        );
+        assert_eq!(pack(&[3, 0, 1, 2], 4, 2), vec![0b1100_0110]);
+        assert_eq!(pack(&[5, 1, 6], 3, 4), vec![0x51, 0x60]);
+        assert_eq!(pack(&[5, 1], 2, 8), vec![5, 1]);
+    }
+
+    #[test]
+    fn next_poll_waits_for_the_next_frame() {
+        let now = 1_000_000;
+        assert_eq!(next_poll_secs(now + 100, 600, now), 105);
+        // unknown or already due, one interval
+        assert_eq!(next_poll_secs(0, 600, now), 600);
+        assert_eq!(next_poll_secs(now - 5, 600, now), 600);
+        // never faster than the minimum
+        assert_eq!(next_poll_secs(now + 2, 600, now), 10);
+        assert_eq!(next_poll_secs(0, 3, now), 10);
+    }
+
+    #[test]
+    fn render_for_device_rejects_odd_rotation() {
+        let device = DeviceProfile {
+            rotation: 45,
+            ..DeviceProfile::default()
+        };
+        assert!(matches!(
+            render_for_device("missing.png", &device),
+            Err(Errors::EinkError(_))
+        ));
+    }
+}
@@ -25,6 +25,54 @@ pub enum Errors {
     #[error("Convert frame count from command-line ffmprobe.exe error. Message:{0}")]
     FrameCountError(String),
 
+    #[error("Invalid daily window. Message:{0}")]
+    DayWindowError(String),
+
+    #[error("Playlist error. Message:{0}")]
+    PlaylistError(String),
+
+    #[error("Watch folder error. Message:{0}")]
+    WatchFolderError(String),
+
+    #[error("Frame source error. Message:{0}")]
+    SourceError(String),
+
+    #[error("Overlay error. Message:{0}")]
+    OverlayError(String),
+
+    #[error("Control command error. Message:{0}")]
+    ControlError(String),
+
+    #[error("Bookmark error. Message:{0}")]
+    BookmarkError(String),
+
+    #[error("Prerender error. Message:{0}")]
+    PrerenderError(String),
+
+    #[error("History error. Message:{0}")]
+    HistoryError(String),
+
+    #[error("Export error. Message:{0}")]
+    ExportError(String),
+
+    #[error("Palette error. Message:{0}")]
+    PaletteError(String),
+
+    #[error("Hook error. Message:{0}")]
+    HookError(String),
+
+    #[error("HTTP error. Message:{0}")]
+    HttpError(String),
+
+    #[error("E-ink error. Message:{0}")]
+    EinkError(String),
+
+    #[error("Monitor error. Message:{0}")]
+    MonitorError(String),
+
+    #[error("Profile error. Message:{0}")]
+    ProfileError(String),
+
     #[error("This Program is only for windows")]
     OSTypeError,
 
@@ -34,6 +82,9 @@ pub enum Errors {
     #[error(transparent)]
     InnerIOError(#[from] std::io::Error),
 
+    #[error(transparent)]
+    ImageError(#[from] image::ImageError),
+
     #[error(transparent)]
     LogError(#[from] fast_log::error::LogError),
 
@@ -0,0 +1,151 @@
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+
+use super::config::Config;
+use super::errors::*;
+use super::overlay::*;
+use super::prerender::for_each_range_frame;
+use super::source::{open_source, DecodeBackend};
+
+const SHEET_THUMB_WIDTH: u32 = 320;
+const SHEET_GAP: u32 = 8;
+const SHEET_BACKGROUND: [u8; 4] = [16, 16, 16, 255];
+
+fn check_movie(movie_path: &str) -> Result<(), Errors> {
+    match std::path::Path::new(movie_path).is_file() {
+        true => Ok(()),
+        false => Err(Errors::ExportError(format!(
+            "{} is not a movie file",
+            movie_path
+        ))),
+    }
+}
+
+// Spacing that spreads `count` samples evenly over the movie.
+fn sample_step(total_frame: u64, count: u64) -> u64 {
+    (total_frame / count.max(1)).max(1)
+}
+
+/// A grid of `count` evenly spaced thumbnails, each labelled with its timecode.
+pub fn export_contact_sheet(
+    conf: &Config,
+    movie_path: &str,
+    count: u64,
+    columns: u32,
+    out_path: &str,
+) -> Result<(), Errors> {
+    check_movie(movie_path)?;
+    let mut source = open_source(movie_path, "", DecodeBackend::Cli)?;
+    let total_frame = source.frame_count()?;
+    let step = sample_step(total_frame, count);
+
+    let mut thumbs: Vec<(u64, RgbaImage)> = Vec::new();
+    for_each_range_frame(
+        movie_path,
+        0,
+        count * step,
+        step,
+        0,
+        &conf.state_file("export_raw"),
+        |index, picture| {
+            if index < count {
+                let picture = image::open(picture)?;
+                let height = picture.height() * SHEET_THUMB_WIDTH / picture.width().max(1);
+                let thumb =
+                    picture.resize_exact(SHEET_THUMB_WIDTH, height.max(1), FilterType::Triangle);
+                thumbs.push((index * step, thumb.to_rgba8()));
+            }
+            Ok(())
+        },
+    )?;
+    if thumbs.is_empty() {
+        return Err(Errors::ExportError(String::from(
+            "no frames were extracted",
+        )));
+    }
+
+    let columns = columns.clamp(1, thumbs.len() as u32);
+    let rows = (thumbs.len() as u32).div_ceil(columns);
+    let thumb_height = thumbs[0].1.height();
+    let mut sheet = RgbaImage::from_pixel(
+        columns * (SHEET_THUMB_WIDTH + SHEET_GAP) + SHEET_GAP,
+        rows * (thumb_height + SHEET_GAP) + SHEET_GAP,
+        Rgba(SHEET_BACKGROUND),
+    );
+    let label_style = TextStyle {
+        size: 18.0,
+        outline_width: 1,
+        anchor: Anchor::BottomRight,
+        margin: 6,
+        box_padding: 0,
+        ..TextStyle::default()
+    };
+    let font = load_font(&label_style.font_path)?;
+    for (i, (frame, mut thumb)) in thumbs.into_iter().enumerate() {
+        let label = format_timecode(source.frame_time_ms(frame));
+        draw_text_block(&mut thumb, &[label], &label_style, &font);
+        let x = SHEET_GAP + (i as u32 % columns) * (SHEET_THUMB_WIDTH + SHEET_GAP);
+        let y = SHEET_GAP + (i as u32 / columns) * (thumb_height + SHEET_GAP);
+        image::imageops::overlay(&mut sheet, &thumb, x as i64, y as i64);
+    }
+    sheet.save_with_format(out_path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+/// A "movie barcode": `width` evenly spaced frames, each squashed into one column of its
+/// average colour.
+pub fn export_barcode(
+    conf: &Config,
+    movie_path: &str,
+    width: u32,
+    height: u32,
+    out_path: &str,
+) -> Result<(), Errors> {
+    check_movie(movie_path)?;
+    let mut source = open_source(movie_path, "", DecodeBackend::Cli)?;
+    let total_frame = source.frame_count()?;
+    let step = sample_step(total_frame, width as u64);
+
+    let mut colors: Vec<Rgba<u8>> = Vec::new();
+    for_each_range_frame(
+        movie_path,
+        0,
+        width as u64 * step,
+        step,
+        0,
+        &conf.state_file("export_raw"),
+        |index, picture| {
+            if index < width as u64 {
+                // a small copy averages the same and is much quicker to walk
+                let small = image::open(picture)?
+                    .resize(64, 64, FilterType::Triangle)
+                    .to_rgb8();
+                let mut sum = [0u64; 3];
+                for pixel in small.pixels() {
+                    for c in 0..3 {
+                        sum[c] += pixel[c] as u64;
+                    }
+                }
+                let n = (small.width() * small.height()).max(1) as u64;
+                colors.push(Rgba([
+                    (sum[0] / n) as u8,
+                    (sum[1] / n) as u8,
+                    (sum[2] / n) as u8,
+                    255,
+                ]));
+            }
+            Ok(())
+        },
+    )?;
+    if colors.is_empty() {
+        return Err(Errors::ExportError(String::from(
+            "no frames were extracted",
+        )));
+    }
+
+    let barcode = RgbaImage::from_fn(colors.len() as u32, height.max(1), |x, _| {
+        colors[x as usize]
+    });
+    barcode.save_with_format(out_path, image::ImageFormat::Png)?;
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use chrono::{DateTime, Duration, Local, LocalResult, NaiveDate, NaiveTime, TimeZone};
+
+use super::errors::*;
+
+pub fn parse_day_time(time: &str) -> Result<NaiveTime, Errors> {
+    match NaiveTime::parse_from_str(time.trim(), "%H:%M") {
+        Ok(t) => Ok(t),
+        Err(e) => Err(Errors::DayWindowError(format!(
+            "\"{}\" is not a HH:MM time. {}",
+            time, e
+        ))),
+    }
+}
+
+// Turn a wall-clock time on a date into a real instant of `zone`.
+// A time repeated when clocks go back takes its first occurrence,
+// a time skipped when clocks go forward moves to the end of the gap.
+fn resolve_local<Tz: TimeZone>(zone: &Tz, date: NaiveDate, time: NaiveTime) -> DateTime<Tz> {
+    let wall = date.and_time(time);
+    match zone.from_local_datetime(&wall) {
+        LocalResult::Single(t) => t,
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        LocalResult::None => {
+            let mut probe = wall;
+            for _ in 0..24 * 60 {
+                probe += Duration::minutes(1);
+                if let Some(t) = zone.from_local_datetime(&probe).earliest() {
+                    return t;
+                }
+            }
+            zone.from_utc_datetime(&wall)
+        }
+    }
+}
+
+/// Map a moment of the day onto the movie, so the whole film plays once per daily window.
+/// The window length is measured in real elapsed time, so days with a DST change stretch
+/// or squeeze the film instead of skipping or repeating scenes. Outside the window the last frame is held.
+pub fn daily_frame<Tz: TimeZone>(
+    now: DateTime<Tz>,
+    start: NaiveTime,
+    end: NaiveTime,
+    total_frame: u64,
+) -> u64 {
+    if total_frame == 0 {
+        return 0;
+    }
+    let last_frame = total_frame - 1;
+
+    let zone = now.timezone();
+    let mut start_date = now.date_naive();
+    let mut window_start = resolve_local(&zone, start_date, start);
+    if window_start > now {
+        start_date = match start_date.pred_opt() {
+            Some(d) => d,
+            None => return last_frame,
+        };
+        window_start = resolve_local(&zone, start_date, start);
+    }
+    let end_date = if end > start {
+        start_date
+    } else {
+        match start_date.succ_opt() {
+            Some(d) => d,
+            None => return last_frame,
+        }
+    };
+    let window_end = resolve_local(&zone, end_date, end);
+
+    let length = (window_end - window_start.clone()).num_milliseconds();
+    let elapsed = (now - window_start).num_milliseconds();
+    if length <= 0 || elapsed >= length {
+        return last_frame;
+    }
+    let frame = (elapsed as u128 * total_frame as u128 / length as u128) as u64;
+    frame.min(last_frame)
+}
+
+pub fn daily_frame_now(day_start: &str, day_end: &str, total_frame: u64) -> Result<u64, Errors> {
+    let start = parse_day_time(day_start)?;
+    let end = parse_day_time(day_end)?;
+    Ok(daily_frame(Local::now(), start, end, total_frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDateTime};
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        // a winter day, no clock change in the windows below
+        let date = NaiveDate::from_ymd_opt(2023, 1, 15).unwrap();
+        resolve_local(
+            &Local,
+            date,
+            NaiveTime::from_hms_opt(hour, minute, 0).unwrap(),
+        )
+    }
+
+    fn time(text: &str) -> NaiveTime {
+        parse_day_time(text).unwrap()
+    }
+
+    #[test]
+    fn daily_frame_follows_the_window() {
+        assert_eq!(daily_frame(at(8, 0), time("08:00"), time("20:00"), 1200), 0);
+        assert_eq!(
+            daily_frame(at(14, 0), time("08:00"), time("20:00"), 1200),
+            600
+        );
+        // outside the window the last frame is held
+        assert_eq!(
+            daily_frame(at(21, 0), time("08:00"), time("20:00"), 1200),
+            1199
+        );
+        assert_eq!(
+            daily_frame(at(7, 0), time("08:00"), time("20:00"), 1200),
+            1199
+        );
+        // a window over midnight
+        assert_eq!(
+            daily_frame(at(2, 0), time("22:00"), time("06:00"), 800),
+            400
+        );
+        assert_eq!(daily_frame(at(12, 0), time("08:00"), time("20:00"), 0), 0);
+    }
+
+    // Central European time in 2023, summer time from 26 March to 29 October 01:00 UTC.
+    #[derive(Debug, Clone, Copy)]
+    struct Cet;
+
+    impl Cet {
+        fn summer(utc: &NaiveDateTime) -> bool {
+            let begin = NaiveDate::from_ymd_opt(2023, 3, 26)
+                .unwrap()
+                .and_hms_opt(1, 0, 0);
+            let end = NaiveDate::from_ymd_opt(2023, 10, 29)
+                .unwrap()
+                .and_hms_opt(1, 0, 0);
+            Some(*utc) >= begin && Some(*utc) < end
+        }
+    }
+
+    impl TimeZone for Cet {
+        type Offset = FixedOffset;
+
+        fn from_offset(_offset: &FixedOffset) -> Cet {
+            Cet
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            // summer time first, it is the earlier instant of a repeated hour
+            let fits: Vec<FixedOffset> = [2, 1]
+                .iter()
+                .map(|h| FixedOffset::east_opt(h * 3600).unwrap())
+                .filter(|o| self.offset_from_utc_datetime(&(*local - *o)) == *o)
+                .collect();
+            match fits.as_slice() {
+                [o] => LocalResult::Single(*o),
+                [a, b] => LocalResult::Ambiguous(*a, *b),
+                _ => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let hours = if Cet::summer(utc) { 2 } else { 1 };
+            FixedOffset::east_opt(hours * 3600).unwrap()
+        }
+    }
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Cet> {
+        let wall = NaiveDate::from_ymd_opt(2023, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap();
+        Cet.from_utc_datetime(&wall)
+    }
+
+    #[test]
+    fn daily_frame_measures_real_time_over_clock_changes() {
+        // 00:00 to 06:00 lasts 5 hours when clocks go forward, 100 frames an hour;
+        // local 04:00 is 3 real hours in
+        assert_eq!(
+            daily_frame(utc(3, 26, 2, 0), time("00:00"), time("06:00"), 500),
+            300
+        );
+        // and 7 hours when they go back; local 05:00 is 6 real hours in
+        assert_eq!(
+            daily_frame(utc(10, 29, 4, 0), time("00:00"), time("06:00"), 700),
+            600
+        );
+    }
+
+    #[test]
+    fn daily_frame_resolves_skipped_and_repeated_times() {
+        // 02:30 does not exist on the spring day, the window starts at 03:00 (01:00 UTC)
+        // and runs to 04:30 (02:30 UTC)
+        assert_eq!(
+            daily_frame(utc(3, 26, 1, 45), time("02:30"), time("04:30"), 100),
+            50
+        );
+        // 02:30 comes twice on the autumn day, the first one (00:30 UTC) starts the window,
+        // which ends at 04:00 (03:00 UTC); the second 02:30 is an hour in
+        assert_eq!(
+            daily_frame(utc(10, 29, 1, 30), time("02:30"), time("04:00"), 150),
+            60
+        );
+    }
+
+    #[test]
+    fn daily_frame_now_checks_times() {
+        assert!(daily_frame_now("8h", "20:00", 100).is_err());
+        assert!(daily_frame_now("08:00", "25:00", 100).is_err());
+        // equal times make the window the whole day
+        assert!(daily_frame_now("00:00", "00:00", 100).unwrap() < 100);
+    }
+}
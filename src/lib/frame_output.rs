@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::errors::*;
+
+const PREFIX: &str = "frame_";
+
+/// Folder the wallpaper pictures are written to. Every frame gets a file name of its own,
+/// so desktops that cache the wallpaper by path still see the change.
+#[derive(Debug, Clone)]
+pub struct FrameOutput {
+    dir: PathBuf,
+    /// Pictures kept besides the one on the desktop.
+    keep_history: usize,
+}
+
+impl FrameOutput {
+    pub fn new(dir: PathBuf, keep_history: usize) -> Result<FrameOutput, Errors> {
+        fs::create_dir_all(&dir)?;
+        Ok(FrameOutput { dir, keep_history })
+    }
+
+    /// Where the next frame is rendered before it is published.
+    pub fn render_path(&self) -> String {
+        self.dir.join("rendering.png").display().to_string()
+    }
+
+    /// Move the rendered picture to its own name in one rename, so the file is either
+    /// complete or not there at all.
+    pub fn publish(&self, frame: u64) -> Result<PathBuf, Errors> {
+        let rendered = PathBuf::from(self.render_path());
+        if !rendered.is_file() {
+            return Err(Errors::FramePictureLost);
+        }
+        let stamp = chrono::Local::now().format("%Y%m%d%H%M%S%3f");
+        let target = self
+            .dir
+            .join(format!("{}{:08}_{}.png", PREFIX, frame, stamp));
+        fs::rename(&rendered, &target)?;
+        Ok(target)
+    }
+
+    /// Published pictures, newest first.
+    pub fn pictures(&self) -> Vec<PathBuf> {
+        let mut pictures: Vec<PathBuf> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| is_published(p))
+                .collect(),
+            Err(e) => {
+                log::warn!("Read frame folder error! Error:{}", e);
+                Vec::new()
+            }
+        };
+        // the time stamp is the last part of the name
+        pictures.sort_by_key(|p| std::cmp::Reverse(stamp_of(p)));
+        pictures
+    }
+
+    /// Remove pictures beyond the current one and the history, oldest first.
+    /// Call once the desktop shows `current`, so it never points at a deleted file.
+    pub fn cleanup(&self, current: &Path) {
+        let mut kept = 0;
+        for picture in self.pictures() {
+            if picture == current || kept < self.keep_history + 1 {
+                kept += 1;
+                continue;
+            }
+            if let Err(e) = fs::remove_file(&picture) {
+                log::warn!("Remove old frame {} error! Error:{}", picture.display(), e);
+            }
+        }
+    }
+}
+
+fn is_published(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => {
+            let name = name.to_string_lossy();
+            name.starts_with(PREFIX) && name.ends_with(".png")
+        }
+        None => false,
+    }
+}
+
+fn stamp_of(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .and_then(|s| s.rsplit('_').next().map(|t| t.to_owned()))
+        .unwrap_or_default()
+}
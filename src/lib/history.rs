@@ -0,0 +1,138 @@
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::config::Config;
+use super::control::ControlCommand;
+use super::errors::*;
+use super::prefetch::movie_key;
+
+const THUMBNAIL_WIDTH: u32 = 160;
+
+/// One frame that was on the desktop.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct HistoryEntry {
+    pub movie_path: String,
+    pub frame: u64,
+    /// Position of the frame in the movie.
+    pub time_ms: u64,
+    /// Local wall-clock time it was shown, "%Y-%m-%d %H:%M:%S".
+    pub shown_at: String,
+    pub picture_path: String,
+    pub thumbnail_path: String,
+}
+
+impl HistoryEntry {
+    pub fn jump_command(&self) -> ControlCommand {
+        ControlCommand::Jump {
+            movie_path: self.movie_path.clone(),
+            frame: self.frame,
+        }
+    }
+}
+
+fn history_files(conf: &Config) -> (PathBuf, PathBuf) {
+    (
+        conf.state_file("history.jsonl"),
+        conf.state_file("history.1.jsonl"),
+    )
+}
+
+fn read_entries(path: &Path) -> Vec<HistoryEntry> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Small copy of the picture, the output folder only keeps a few frames.
+fn make_thumbnail(conf: &Config, entry: &HistoryEntry) -> Result<String, Errors> {
+    let dir = conf.state_file("history_thumbs");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "{}_{}.png",
+        movie_key(&entry.movie_path),
+        entry.frame
+    ));
+    if !path.is_file() {
+        let picture = image::open(&entry.picture_path)?;
+        let height = picture.height() * THUMBNAIL_WIDTH / picture.width().max(1);
+        picture
+            .resize(THUMBNAIL_WIDTH, height.max(1), FilterType::Triangle)
+            .save_with_format(&path, image::ImageFormat::Png)?;
+    }
+    Ok(path.display().to_string())
+}
+
+/// Add a shown frame to the history. Once the current file holds the configured number
+/// of entries it becomes history.1.jsonl, replacing the one before.
+pub fn append_history(conf: &Config, mut entry: HistoryEntry) -> Result<(), Errors> {
+    entry.thumbnail_path = match make_thumbnail(conf, &entry) {
+        Ok(path) => path,
+        Err(e) => {
+            log::warn!("Make history thumbnail error! Error:{}", e);
+            String::new()
+        }
+    };
+    let line = serde_json::to_string(&entry).map_err(|e| Errors::HistoryError(e.to_string()))?;
+
+    let (current, rotated) = history_files(conf);
+    let rotate = read_entries(&current).len() >= conf.get_history_limit() as usize;
+    if rotate {
+        fs::rename(&current, &rotated)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&current)?;
+    writeln!(file, "{}", line)?;
+    // pruned once the new entry is written, its thumbnail is listed by then
+    if rotate {
+        remove_stale_thumbnails(conf);
+    }
+    Ok(())
+}
+
+// Thumbnails no longer named by either history file.
+fn remove_stale_thumbnails(conf: &Config) {
+    let entries = load_history(conf);
+    let dir = conf.state_file("history_thumbs");
+    let listed = match fs::read_dir(&dir) {
+        Ok(listed) => listed,
+        Err(_) => return,
+    };
+    for file in listed.flatten() {
+        let path = file.path().display().to_string();
+        if !entries.iter().any(|e| e.thumbnail_path == path) {
+            if let Err(e) = fs::remove_file(&path) {
+                log::warn!("Remove history thumbnail {} error! Error:{}", path, e);
+            }
+        }
+    }
+}
+
+/// Every remembered entry, oldest first.
+pub fn load_history(conf: &Config) -> Vec<HistoryEntry> {
+    let (current, rotated) = history_files(conf);
+    let mut entries = read_entries(&rotated);
+    entries.extend(read_entries(&current));
+    entries
+}
+
+/// The latest `count` entries, newest first, optionally only those of movies whose
+/// path contains `movie_filter`.
+pub fn recent_history(conf: &Config, count: usize, movie_filter: &str) -> Vec<HistoryEntry> {
+    load_history(conf)
+        .into_iter()
+        .rev()
+        .filter(|e| movie_filter.is_empty() || e.movie_path.contains(movie_filter))
+        .take(count)
+        .collect()
+}
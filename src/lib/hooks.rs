@@ -0,0 +1,157 @@
+use serde::Serialize;
+
+use std::io::Write;
+use std::os::windows::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::config::Config;
+use super::errors::*;
+use super::render::FrameInfo;
+
+/// What happens to the wallpaper loop when a hook fails or runs past its timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HookFailure {
+    Ignore,
+    #[default]
+    Warn,
+    /// Stop playback like any other error in the loop.
+    Abort,
+}
+impl HookFailure {
+    pub const ALL: [HookFailure; 3] = [HookFailure::Ignore, HookFailure::Warn, HookFailure::Abort];
+}
+impl std::fmt::Display for HookFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                HookFailure::Ignore => "ignore",
+                HookFailure::Warn => "warn",
+                HookFailure::Abort => "abort",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    /// Before the frame is extracted.
+    Pre,
+    /// After the wallpaper was set.
+    Post,
+}
+
+/// Frame metadata handed to a hook, as JSON on stdin and as SLOWMOVIE_* variables.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HookEvent {
+    pub stage: String,
+    pub movie_path: String,
+    pub frame: u64,
+    pub total_frame: u64,
+    pub time_ms: u64,
+    /// Empty for the pre-frame hook, the picture does not exist yet.
+    pub picture_path: String,
+}
+impl HookEvent {
+    pub fn new(info: &FrameInfo, picture_path: &str) -> HookEvent {
+        HookEvent {
+            stage: String::new(),
+            movie_path: info.movie_path.clone(),
+            frame: info.frame,
+            total_frame: info.total_frame,
+            time_ms: info.time_ms,
+            picture_path: picture_path.to_owned(),
+        }
+    }
+}
+
+fn kill_tree(child: &mut Child) {
+    // cmd /C leaves the real command running when only cmd is killed
+    let killed = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &child.id().to_string()])
+        .creation_flags(0x08000000)
+        .status();
+    if killed.is_err() {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+/// Run a hook command and wait at most `timeout` for it.
+pub fn run_hook(command: &str, event: &HookEvent, timeout: Duration) -> Result<(), Errors> {
+    let json = serde_json::to_string(event).map_err(|e| Errors::HookError(e.to_string()))?;
+    log::info!("{} frame hook : {}", event.stage, command);
+    let mut child = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", command])
+            .env("SLOWMOVIE_STAGE", &event.stage)
+            .env("SLOWMOVIE_MOVIE", &event.movie_path)
+            .env("SLOWMOVIE_FRAME", event.frame.to_string())
+            .env("SLOWMOVIE_TOTAL_FRAMES", event.total_frame.to_string())
+            .env("SLOWMOVIE_TIME_MS", event.time_ms.to_string())
+            .env("SLOWMOVIE_PICTURE", &event.picture_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .creation_flags(0x08000000)
+            .spawn()?
+    } else {
+        return Err(Errors::OSTypeError);
+    };
+    // a hook that never reads stdin must not block us, so a failed write is fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(json.as_bytes());
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return match status.success() {
+                true => Ok(()),
+                false => Err(Errors::HookError(format!(
+                    "\"{}\" finished with:{}",
+                    command, status
+                ))),
+            };
+        }
+        if Instant::now() >= deadline {
+            kill_tree(&mut child);
+            return Err(Errors::HookError(format!(
+                "\"{}\" timed out after {} seconds",
+                command,
+                timeout.as_secs()
+            )));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Run the configured hook for `stage`, if any, and apply the failure policy.
+/// Only an aborting policy hands the error back.
+pub fn run_frame_hook(conf: &Config, stage: HookStage, mut event: HookEvent) -> Result<(), Errors> {
+    let command = match stage {
+        HookStage::Pre => conf.get_pre_frame_hook(),
+        HookStage::Post => conf.get_post_frame_hook(),
+    };
+    if command.trim().is_empty() {
+        return Ok(());
+    }
+    event.stage = match stage {
+        HookStage::Pre => String::from("pre"),
+        HookStage::Post => String::from("post"),
+    };
+    let timeout = Duration::from_secs(conf.get_hook_timeout_secs().max(1));
+    match run_hook(&command, &event, timeout) {
+        Ok(_) => Ok(()),
+        Err(e) => match conf.get_hook_failure() {
+            HookFailure::Ignore => Ok(()),
+            HookFailure::Warn => {
+                log::warn!("Frame hook error! Error:{}", e);
+                Ok(())
+            }
+            HookFailure::Abort => Err(e),
+        },
+    }
+}
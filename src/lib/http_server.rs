@@ -0,0 +1,373 @@
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::config::Config;
+use super::control::*;
+use super::eink::*;
+use super::errors::*;
+
+/// What the wallpaper loop shows right now, answered on `GET /status`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct FrameStatus {
+    pub movie_path: String,
+    pub frame: u64,
+    pub total_frame: u64,
+    pub time_ms: u64,
+    pub duration_ms: u64,
+    pub picture_path: String,
+    pub shown_at: String,
+    pub paused: bool,
+    pub interval_secs: u64,
+    /// Unix time in seconds when the next frame is due, 0 when unknown.
+    pub next_frame_at: i64,
+}
+
+// A device's packed frame, with the picture and profile it was made from. Err holds why
+// packing failed, answered until the picture or the profile changes.
+type Packed = (String, DeviceProfile, Result<Arc<PackedFrame>, String>);
+
+// What the request threads share with the loop.
+#[derive(Default)]
+struct Shared {
+    status: FrameStatus,
+    devices: Vec<DeviceProfile>,
+    /// Last packed frame per device.
+    packed: HashMap<String, Packed>,
+}
+
+// Requests answered at once, a slow client only holds up one of them.
+const REQUEST_THREADS: usize = 4;
+
+/// Embedded HTTP server for frame fetching and playback control. It stops when dropped.
+pub struct FrameServer {
+    address: String,
+    shared: Arc<Mutex<Shared>>,
+    server: Arc<Server>,
+    workers: Vec<JoinHandle<()>>,
+    // wakes the packer after a new frame or device list, dropping it ends the packer
+    repack: Option<Sender<()>>,
+    packer: Option<JoinHandle<()>>,
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("header is not ascii")
+}
+
+fn text_response(code: u16, text: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(text)
+        .with_status_code(code)
+        .with_header(header("Content-Type", "text/plain; charset=utf-8"))
+}
+
+fn json_response<T: Serialize>(value: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    match serde_json::to_string(value) {
+        Ok(text) => {
+            Response::from_string(text).with_header(header("Content-Type", "application/json"))
+        }
+        Err(e) => text_response(500, &e.to_string()),
+    }
+}
+
+// Undo the %XX escapes of a URL path segment, None when they are broken or not UTF-8.
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// The frame number of `POST /seek`, from `?frame=N` or the request body.
+fn seek_frame(request: &mut Request) -> Option<u64> {
+    let query = request.url().split_once('?').map(|(_, q)| q.to_owned());
+    if let Some(query) = query {
+        for pair in query.split('&') {
+            if let Some(("frame", value)) = pair.split_once('=') {
+                return value.trim().parse::<u64>().ok();
+            }
+        }
+    }
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body).ok()?;
+    body.trim().parse::<u64>().ok()
+}
+
+// Pack the shown frame for every device whose cached one is stale. Runs on a thread of
+// its own, so neither the loop nor the requests wait for the resizing and dithering.
+fn pack_frames(shared: &Mutex<Shared>, repack: Receiver<()>) {
+    while repack.recv().is_ok() {
+        // one pass covers every wakeup queued meanwhile
+        while repack.try_recv().is_ok() {}
+        let (picture_path, devices) = match shared.lock() {
+            Ok(s) => (s.status.picture_path.clone(), s.devices.clone()),
+            Err(_) => return,
+        };
+        if picture_path.is_empty() {
+            continue;
+        }
+        for device in devices {
+            let fresh = match shared.lock() {
+                Ok(s) => matches!(s.packed.get(&device.name),
+                    Some((picture, profile, _)) if *picture == picture_path && *profile == device),
+                Err(_) => return,
+            };
+            if fresh {
+                continue;
+            }
+            let frame = render_for_device(&picture_path, &device).map(Arc::new);
+            if let Err(e) = &frame {
+                log::warn!("Pack frame for {} error! Error:{}", device.name, e);
+            }
+            if let Ok(mut s) = shared.lock() {
+                s.packed.insert(
+                    device.name.clone(),
+                    (
+                        picture_path.clone(),
+                        device,
+                        frame.map_err(|e| e.to_string()),
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn device_response(
+    shared: &Mutex<Shared>,
+    current: &FrameStatus,
+    name: &str,
+    file: &str,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    if current.picture_path.is_empty() {
+        return text_response(503, "no frame shown yet");
+    }
+    let (device, cached) = match shared.lock() {
+        Ok(s) => (
+            s.devices.iter().find(|d| d.name == name).cloned(),
+            s.packed.get(name).cloned(),
+        ),
+        Err(_) => (None, None),
+    };
+    let device = match device {
+        Some(d) => d,
+        None => return text_response(404, &format!("no device named {}", name)),
+    };
+    let frame = match cached {
+        Some((picture, profile, frame)) if picture == current.picture_path && profile == device => {
+            match frame {
+                Ok(f) => f,
+                Err(e) => return text_response(500, &e),
+            }
+        }
+        // the packer is still at it
+        _ => {
+            return text_response(503, "frame is being packed")
+                .with_header(header("Retry-After", "5"))
+        }
+    };
+    let next_poll = next_poll_secs(
+        current.next_frame_at,
+        current.interval_secs,
+        chrono::Local::now().timestamp(),
+    );
+    let response = match file {
+        "frame.bin" => Response::from_data(frame.data.clone())
+            .with_header(header("Content-Type", "application/octet-stream")),
+        _ => Response::from_data(frame.preview.clone())
+            .with_header(header("Content-Type", "image/png")),
+    };
+    response
+        .with_header(header("Cache-Control", "no-cache"))
+        .with_header(header("X-Frame", &current.frame.to_string()))
+        .with_header(header("X-Width", &frame.width.to_string()))
+        .with_header(header("X-Height", &frame.height.to_string()))
+        .with_header(header("X-Bits", &frame.bits.to_string()))
+        .with_header(header("X-Next-Poll", &next_poll.to_string()))
+}
+
+fn handle(conf: &Config, shared: &Mutex<Shared>, mut request: Request) {
+    let path = request.url().split('?').next().unwrap_or("").to_owned();
+    let current = shared.lock().map(|s| s.status.clone()).unwrap_or_default();
+    let device_file = path
+        .strip_prefix("/device/")
+        .and_then(|rest| rest.split_once('/'))
+        .filter(|(_, file)| *file == "frame.bin" || *file == "frame.png");
+    let response = match (request.method(), path.as_str()) {
+        (Method::Get, _) if device_file.is_some() => {
+            let (name, file) = device_file.unwrap_or_default();
+            // clients escape names with spaces or other characters
+            match percent_decode(name) {
+                Some(name) => device_response(shared, &current, &name, file),
+                None => text_response(400, "bad device name"),
+            }
+        }
+        (Method::Get, "/frame.png") => match fs::read(&current.picture_path) {
+            Ok(data) => Response::from_data(data)
+                .with_header(header("Content-Type", "image/png"))
+                .with_header(header("Cache-Control", "no-cache"))
+                .with_header(header("X-Frame", &current.frame.to_string())),
+            Err(_) => text_response(503, "no frame shown yet"),
+        },
+        (Method::Get, "/status") => json_response(&current),
+        (Method::Post, "/next" | "/prev" | "/pause" | "/resume" | "/seek") => {
+            let command = match &path[1..] {
+                "seek" => seek_frame(&mut request).map(ControlCommand::Seek),
+                name => ControlCommand::parse(name, None),
+            };
+            match command {
+                Some(command) => match push_command(conf, command.clone()) {
+                    Ok(_) => text_response(202, &format!("{:?} queued", command)),
+                    Err(e) => text_response(500, &e.to_string()),
+                },
+                None => text_response(400, "seek needs a frame number"),
+            }
+        }
+        (_, "/frame.png" | "/status" | "/next" | "/prev" | "/pause" | "/resume" | "/seek") => {
+            text_response(405, "method not allowed")
+        }
+        _ => text_response(404, "not found"),
+    };
+    if let Err(e) = request.respond(response) {
+        log::warn!("Answer HTTP request error! Error:{}", e);
+    }
+}
+
+impl FrameServer {
+    pub fn start(conf: &Config) -> Result<FrameServer, Errors> {
+        let address = conf.get_http_address();
+        let server = Server::http(address.as_str())
+            .map_err(|e| Errors::HttpError(format!("listen on {} failed. {}", address, e)))?;
+        let server = Arc::new(server);
+        // answer with the last picture of an earlier run until the first frame is set
+        let shared = Arc::new(Mutex::new(Shared {
+            status: FrameStatus {
+                movie_path: conf.get_movie_path(),
+                frame: conf.get_shown_frame(),
+                picture_path: conf.get_shown_picture(),
+                paused: conf.is_paused(),
+                interval_secs: u64::from(conf.get_time_interval()),
+                ..FrameStatus::default()
+            },
+            devices: conf.get_eink_devices(),
+            packed: HashMap::new(),
+        }));
+
+        let workers = (0..REQUEST_THREADS)
+            .map(|_| {
+                let (conf, worker_server, worker_shared) =
+                    (conf.clone(), Arc::clone(&server), Arc::clone(&shared));
+                thread::spawn(move || {
+                    for request in worker_server.incoming_requests() {
+                        handle(&conf, &worker_shared, request);
+                    }
+                })
+            })
+            .collect();
+        let (repack, wakeups) = channel();
+        let packer_shared = Arc::clone(&shared);
+        let packer = thread::spawn(move || pack_frames(&packer_shared, wakeups));
+        // the picture of an earlier run is served too
+        let _ = repack.send(());
+        log::info!("HTTP server listens on {}", address);
+        Ok(FrameServer {
+            address,
+            shared,
+            server,
+            workers,
+            repack: Some(repack),
+            packer: Some(packer),
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn wake_packer(&self) {
+        if let Some(repack) = &self.repack {
+            let _ = repack.send(());
+        }
+    }
+
+    /// Publish a newly shown frame, devices get it packed in the background.
+    pub fn update(&self, status: FrameStatus) {
+        if let Ok(mut s) = self.shared.lock() {
+            s.status = status;
+        }
+        self.wake_packer();
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        if let Ok(mut s) = self.shared.lock() {
+            s.status.paused = paused;
+        }
+    }
+
+    pub fn set_next_frame_at(&self, unix_secs: i64) {
+        if let Ok(mut s) = self.shared.lock() {
+            s.status.next_frame_at = unix_secs;
+        }
+    }
+
+    pub fn set_devices(&self, devices: Vec<DeviceProfile>) {
+        let changed = match self.shared.lock() {
+            Ok(mut s) if s.devices != devices => {
+                s.devices = devices;
+                true
+            }
+            _ => false,
+        };
+        if changed {
+            self.wake_packer();
+        }
+    }
+}
+
+impl Drop for FrameServer {
+    fn drop(&mut self) {
+        // every unblock wakes one waiting worker
+        for _ in 0..self.workers.len() {
+            self.server.unblock();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.repack = None;
+        if let Some(packer) = self.packer.take() {
+            let _ = packer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_device_names() {
+        assert_eq!(percent_decode("kitchen").as_deref(), Some("kitchen"));
+        assert_eq!(
+            percent_decode("living%20room").as_deref(),
+            Some("living room")
+        );
+        assert_eq!(percent_decode("K%C3%BCche").as_deref(), Some("K\u{fc}che"));
+        assert_eq!(percent_decode("bad%2"), None);
+        assert_eq!(percent_decode("bad%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+}
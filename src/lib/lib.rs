@@ -1,11 +1,82 @@
+pub mod bookmark;
+pub use bookmark::*;
+
 pub mod config;
 pub use config::*;
 
+pub mod control;
+pub use control::*;
+
+pub mod eink;
+pub use eink::*;
+
 pub mod errors;
 pub use errors::*;
 
+pub mod export;
+pub use export::*;
+
+pub mod film_clock;
+pub use film_clock::*;
+
+pub mod frame_output;
+pub use frame_output::*;
+
+pub mod history;
+pub use history::*;
+
+pub mod hooks;
+pub use hooks::*;
+
+pub mod http_server;
+pub use http_server::*;
+
+#[cfg(feature = "libav")]
+pub mod libav;
+#[cfg(feature = "libav")]
+pub use libav::*;
+
+pub mod monitor;
+pub use monitor::*;
+
+pub mod overlay;
+pub use overlay::*;
+
+pub mod palette;
+pub use palette::*;
+
+pub mod playback;
+pub use playback::*;
+
+pub mod playlist;
+pub use playlist::*;
+
+pub mod prefetch;
+pub use prefetch::*;
+
+pub mod prerender;
+pub use prerender::*;
+
+pub mod profile;
+pub use profile::*;
+
+pub mod render;
+pub use render::*;
+
+pub mod source;
+pub use source::*;
+
+pub mod subtitle;
+pub use subtitle::*;
+
+pub mod timelapse;
+pub use timelapse::*;
+
 pub mod utils;
 pub use utils::*;
 
 pub mod video_process;
 pub use video_process::*;
+
+pub mod watch_folder;
+pub use watch_folder::*;
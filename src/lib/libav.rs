@@ -0,0 +1,191 @@
+use ffmpeg_next as ffmpeg;
+
+use ffmpeg::format::context::Input;
+use ffmpeg::format::Pixel;
+use ffmpeg::media::Type;
+use ffmpeg::software::scaling::{context::Context, flag::Flags};
+use ffmpeg::util::frame::video::Video;
+
+use super::errors::*;
+use super::source::FrameSource;
+use super::video_process::Chapter;
+
+// Decoding forward is cheaper than seeking for small jumps.
+const MAX_DECODE_AHEAD: u64 = 250;
+
+fn libav_error(e: ffmpeg::Error) -> Errors {
+    Errors::SourceError(format!("libav: {}", e))
+}
+
+/// A movie decoded in process through the FFmpeg libraries.
+/// The file stays open between ticks, so stepping one frame forward does not reopen or reseek it.
+pub struct LibavSource {
+    input: Input,
+    stream_index: usize,
+    decoder: ffmpeg::decoder::Video,
+    time_base: f64,
+    start_time: i64,
+    fps: f64,
+    total_frame: u64,
+    chapters: Vec<Chapter>,
+    /// Index of the frame the decoder handed out last, None right after opening or seeking.
+    last_index: Option<u64>,
+}
+
+impl LibavSource {
+    pub fn open(movie_path: &str) -> Result<LibavSource, Errors> {
+        ffmpeg::init().map_err(libav_error)?;
+        let input = ffmpeg::format::input(&movie_path).map_err(libav_error)?;
+        let stream = match input.streams().best(Type::Video) {
+            Some(s) => s,
+            None => return Err(libav_error(ffmpeg::Error::StreamNotFound)),
+        };
+        let stream_index = stream.index();
+        let time_base = f64::from(stream.time_base());
+        let start_time = match stream.start_time() {
+            ffmpeg::ffi::AV_NOPTS_VALUE => 0,
+            t => t,
+        };
+        let fps = match f64::from(stream.avg_frame_rate()) {
+            r if r > 0.0 => r,
+            _ => 24.0,
+        };
+        let total_frame = match stream.frames() {
+            n if n > 0 => n as u64,
+            _ => (stream.duration().max(0) as f64 * time_base * fps) as u64,
+        };
+
+        let chapters = input
+            .chapters()
+            .map(|c| {
+                let tb = f64::from(c.time_base()) * 1000.0;
+                Chapter {
+                    start_ms: (c.start().max(0) as f64 * tb) as u64,
+                    end_ms: (c.end().max(0) as f64 * tb) as u64,
+                    title: c.metadata().get("title").unwrap_or("").to_owned(),
+                }
+            })
+            .collect();
+
+        let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())
+            .map_err(libav_error)?;
+        let decoder = context.decoder().video().map_err(libav_error)?;
+
+        Ok(LibavSource {
+            input,
+            stream_index,
+            decoder,
+            time_base,
+            start_time,
+            fps,
+            total_frame,
+            chapters,
+            last_index: None,
+        })
+    }
+
+    fn seek_to(&mut self, frame: u64) -> Result<(), Errors> {
+        let start_us = self.start_time as f64 * self.time_base * 1_000_000.0;
+        let target = (start_us + frame as f64 / self.fps * 1_000_000.0) as i64;
+        self.input.seek(target, ..target).map_err(libav_error)?;
+        self.decoder.flush();
+        self.last_index = None;
+        Ok(())
+    }
+
+    fn frame_index(&self, decoded: &Video) -> u64 {
+        match decoded.timestamp() {
+            Some(ts) => {
+                let secs = (ts - self.start_time) as f64 * self.time_base;
+                (secs * self.fps).round().max(0.0) as u64
+            }
+            // no timestamp, count on from the last frame
+            None => self.last_index.map_or(0, |i| i + 1),
+        }
+    }
+
+    fn save_frame(&self, decoded: &Video, picture_path: &str) -> Result<(), Errors> {
+        let (width, height) = (decoded.width(), decoded.height());
+        let mut scaler = Context::get(
+            decoded.format(),
+            width,
+            height,
+            Pixel::RGB24,
+            width,
+            height,
+            Flags::BILINEAR,
+        )
+        .map_err(libav_error)?;
+        let mut rgb = Video::empty();
+        scaler.run(decoded, &mut rgb).map_err(libav_error)?;
+
+        let stride = rgb.stride(0);
+        let row = width as usize * 3;
+        let mut pixels = Vec::with_capacity(row * height as usize);
+        for line in rgb.data(0).chunks(stride).take(height as usize) {
+            pixels.extend_from_slice(&line[..row]);
+        }
+        let picture = match image::RgbImage::from_raw(width, height, pixels) {
+            Some(p) => p,
+            None => return Err(Errors::FramePictureLost),
+        };
+        picture.save_with_format(picture_path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+}
+
+impl FrameSource for LibavSource {
+    fn frame_count(&mut self) -> Result<u64, Errors> {
+        Ok(self.total_frame)
+    }
+
+    fn extract_frame(&mut self, frame: u64, picture_path: &str) -> Result<(), Errors> {
+        let near = match self.last_index {
+            Some(last) => frame > last && frame - last <= MAX_DECODE_AHEAD,
+            None => false,
+        };
+        if !near {
+            self.seek_to(frame)?;
+        }
+
+        let mut decoded = Video::empty();
+        let mut eof = false;
+        loop {
+            while self.decoder.receive_frame(&mut decoded).is_ok() {
+                let index = self.frame_index(&decoded);
+                self.last_index = Some(index);
+                if index >= frame {
+                    return self.save_frame(&decoded, picture_path);
+                }
+            }
+            if eof {
+                return Err(Errors::FramePictureLost);
+            }
+
+            let mut sent = false;
+            for (stream, packet) in self.input.packets() {
+                if stream.index() == self.stream_index {
+                    self.decoder.send_packet(&packet).map_err(libav_error)?;
+                    sent = true;
+                    break;
+                }
+            }
+            if !sent {
+                self.decoder.send_eof().map_err(libav_error)?;
+                eof = true;
+            }
+        }
+    }
+
+    fn frame_time_ms(&mut self, frame: u64) -> u64 {
+        (frame as f64 * 1000.0 / self.fps) as u64
+    }
+
+    fn chapters(&mut self) -> Vec<Chapter> {
+        self.chapters.clone()
+    }
+
+    fn keep_open(&self) -> bool {
+        true
+    }
+}
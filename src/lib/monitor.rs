@@ -0,0 +1,459 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::os::windows::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use super::config::Config;
+use super::errors::*;
+use super::render::{finish_frame, FrameInfo};
+use super::source::{open_source, FrameSource};
+
+/// How frames are laid out when there is more than one screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MonitorMode {
+    /// One frame, placed on every screen by the wallpaper style Windows already uses.
+    #[default]
+    Single,
+    /// One frame stretched over all screens as one wall.
+    Span,
+    /// Every screen plays its own movie, screens without one show the main frame.
+    PerMonitor,
+}
+impl MonitorMode {
+    pub const ALL: [MonitorMode; 3] = [
+        MonitorMode::Single,
+        MonitorMode::Span,
+        MonitorMode::PerMonitor,
+    ];
+}
+impl std::fmt::Display for MonitorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                MonitorMode::Single => "single",
+                MonitorMode::Span => "span",
+                MonitorMode::PerMonitor => "per monitor",
+            }
+        )
+    }
+}
+
+/// One screen in desktop coordinates. A size of 0 takes the geometry of the detected
+/// screen at the same position in the list.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Monitor {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Movie of this screen in per-monitor mode, empty shows the main frame.
+    pub movie_path: String,
+    /// Next frame of `movie_path`.
+    pub frame_count: u64,
+}
+
+/// Screens as Windows reports them, in physical pixels.
+pub fn detect_monitors() -> Result<Vec<Monitor>, Errors> {
+    let script = "Add-Type -MemberDefinition '[DllImport(\"user32.dll\")] public static extern bool SetProcessDPIAware();' -Name Dpi -Namespace SlowMovie; \
+        [SlowMovie.Dpi]::SetProcessDPIAware() | Out-Null; \
+        Add-Type -AssemblyName System.Windows.Forms; \
+        [System.Windows.Forms.Screen]::AllScreens | ForEach-Object { '{0},{1},{2},{3},{4}' -f $_.Bounds.X,$_.Bounds.Y,$_.Bounds.Width,$_.Bounds.Height,$_.DeviceName }";
+    let output = if cfg!(target_os = "windows") {
+        Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", script])
+            .creation_flags(0x08000000)
+            .stdout(Stdio::piped())
+            .output()?
+    } else {
+        return Err(Errors::OSTypeError);
+    };
+    let text = String::from_utf8_lossy(&output.stdout).to_string();
+    let monitors: Vec<Monitor> = text
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.trim().splitn(5, ',').collect();
+            if parts.len() < 4 {
+                return None;
+            }
+            Some(Monitor {
+                x: parts[0].parse().ok()?,
+                y: parts[1].parse().ok()?,
+                width: parts[2].parse().ok()?,
+                height: parts[3].parse().ok()?,
+                name: parts.get(4).unwrap_or(&"").to_string(),
+                ..Monitor::default()
+            })
+        })
+        .collect();
+    match monitors.is_empty() {
+        true => Err(Errors::MonitorError(String::from("no screen was detected"))),
+        false => Ok(monitors),
+    }
+}
+
+// One value of the desktop key, as `reg query` prints it: "    WallpaperStyle    REG_SZ    10".
+fn desktop_value(name: &str) -> Result<String, Errors> {
+    let output = if cfg!(target_os = "windows") {
+        Command::new("reg")
+            .args(["query", r"HKCU\Control Panel\Desktop", "/v", name])
+            .creation_flags(0x08000000)
+            .stdout(Stdio::piped())
+            .output()?
+    } else {
+        return Err(Errors::OSTypeError);
+    };
+    let text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .find(|parts| parts.len() == 3 && parts[0].eq_ignore_ascii_case(name))
+        .map(|parts| parts[2].to_string())
+        .ok_or_else(|| Errors::MonitorError(format!("desktop value {} is not set", name)))
+}
+
+/// Switch the desktop to spanning one picture over all screens. The style the user had
+/// before the first switch is kept in the profile, so single mode can bring it back.
+pub fn span_wallpaper(conf: &Config) -> Result<(), Errors> {
+    let saved = conf.state_file("wallpaper_style.txt");
+    if !saved.exists() {
+        let style = desktop_value("WallpaperStyle")?;
+        let tile = desktop_value("TileWallpaper")?;
+        std::fs::write(&saved, format!("{},{}", style, tile))?;
+    }
+    wallpaper::set_mode(wallpaper::Mode::Span)
+        .map_err(|e| Errors::MonitorError(format!("Set span style failed. {}", e)))
+}
+
+/// Give the desktop back the style saved by `span_wallpaper`. Nothing was saved when the
+/// wallpaper never spanned the screens, and the style is left as the user set it.
+pub fn restore_wallpaper(conf: &Config) -> Result<(), Errors> {
+    let saved = conf.state_file("wallpaper_style.txt");
+    let text = match std::fs::read_to_string(&saved) {
+        Ok(text) => text,
+        Err(_) => return Ok(()),
+    };
+    let mode = match text.trim().split_once(',') {
+        Some((_, "1")) => wallpaper::Mode::Tile,
+        Some(("0", _)) => wallpaper::Mode::Center,
+        Some(("2", _)) => wallpaper::Mode::Stretch,
+        Some(("6", _)) => wallpaper::Mode::Fit,
+        Some(("22", _)) => wallpaper::Mode::Span,
+        _ => wallpaper::Mode::Crop,
+    };
+    wallpaper::set_mode(mode)
+        .map_err(|e| Errors::MonitorError(format!("Restore wallpaper style failed. {}", e)))?;
+    std::fs::remove_file(&saved)?;
+    Ok(())
+}
+
+// The canvas Windows spans a wallpaper over: the box around every screen.
+fn desktop_bounds(monitors: &[Monitor]) -> (i32, i32, u32, u32) {
+    let left = monitors.iter().map(|m| m.x).min().unwrap_or(0);
+    let top = monitors.iter().map(|m| m.y).min().unwrap_or(0);
+    let right = monitors
+        .iter()
+        .map(|m| m.x + m.width as i32)
+        .max()
+        .unwrap_or(0);
+    let bottom = monitors
+        .iter()
+        .map(|m| m.y + m.height as i32)
+        .max()
+        .unwrap_or(0);
+    (
+        left,
+        top,
+        (right - left).max(1) as u32,
+        (bottom - top).max(1) as u32,
+    )
+}
+
+/// One frame over all screens as if they were a single wall. `bezel` is the picture in
+/// pixels hidden between two neighbouring screens, [horizontal, vertical], so lines keep
+/// running straight across the frames.
+pub fn compose_span(picture: &DynamicImage, monitors: &[Monitor], bezel: [u32; 2]) -> RgbImage {
+    let (left, top, width, height) = desktop_bounds(monitors);
+    // every screen is pushed right and down by the bezels of the screens before it
+    let gaps = |edge: i32, ends: Vec<i32>, gap: u32| {
+        let mut ends: Vec<i32> = ends.into_iter().filter(|e| *e <= edge).collect();
+        ends.sort_unstable();
+        ends.dedup();
+        ends.len() as i64 * gap as i64
+    };
+    let walls: Vec<(i64, i64)> = monitors
+        .iter()
+        .map(|m| {
+            let rights = monitors.iter().map(|o| o.x + o.width as i32).collect();
+            let bottoms = monitors.iter().map(|o| o.y + o.height as i32).collect();
+            (
+                (m.x - left) as i64 + gaps(m.x, rights, bezel[0]),
+                (m.y - top) as i64 + gaps(m.y, bottoms, bezel[1]),
+            )
+        })
+        .collect();
+    let wall_width = monitors
+        .iter()
+        .zip(walls.iter())
+        .map(|(m, w)| w.0 + m.width as i64)
+        .max()
+        .unwrap_or(1);
+    let wall_height = monitors
+        .iter()
+        .zip(walls.iter())
+        .map(|(m, w)| w.1 + m.height as i64)
+        .max()
+        .unwrap_or(1);
+    let wall = picture
+        .resize_to_fill(
+            wall_width.max(1) as u32,
+            wall_height.max(1) as u32,
+            FilterType::Lanczos3,
+        )
+        .to_rgb8();
+
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb([0, 0, 0]));
+    for (m, (wx, wy)) in monitors.iter().zip(walls.iter()) {
+        let part = image::imageops::crop_imm(&wall, *wx as u32, *wy as u32, m.width, m.height);
+        image::imageops::replace(
+            &mut canvas,
+            &part.to_image(),
+            (m.x - left) as i64,
+            (m.y - top) as i64,
+        );
+    }
+    canvas
+}
+
+/// Every screen with a picture of its own, each filled to its screen.
+pub fn compose_monitors(pictures: &[(&Monitor, DynamicImage)]) -> RgbImage {
+    let monitors: Vec<Monitor> = pictures.iter().map(|(m, _)| (*m).clone()).collect();
+    let (left, top, width, height) = desktop_bounds(&monitors);
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb([0, 0, 0]));
+    for (m, picture) in pictures.iter() {
+        let fitted = picture
+            .resize_to_fill(m.width.max(1), m.height.max(1), FilterType::Lanczos3)
+            .to_rgb8();
+        image::imageops::replace(
+            &mut canvas,
+            &fitted,
+            (m.x - left) as i64,
+            (m.y - top) as i64,
+        );
+    }
+    canvas
+}
+
+/// Screen layout and per-screen movies of the wallpaper loop, kept between ticks.
+pub struct MonitorSet {
+    detected: Vec<Monitor>,
+    detected_at: Option<Instant>,
+    sources: HashMap<String, (Box<dyn FrameSource>, u64)>,
+    // which of the two composite files was written last
+    flip: bool,
+}
+
+impl Default for MonitorSet {
+    fn default() -> Self {
+        MonitorSet::new()
+    }
+}
+
+impl MonitorSet {
+    /// Screens are looked up again after this long, they may have been plugged in or out.
+    const DETECT_EVERY: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new() -> MonitorSet {
+        MonitorSet {
+            detected: Vec::new(),
+            detected_at: None,
+            sources: HashMap::new(),
+            flip: false,
+        }
+    }
+
+    /// The configured screens, with detected geometry where none was declared.
+    pub fn layout(&mut self, conf: &Config) -> Result<Vec<Monitor>, Errors> {
+        let declared = conf.get_monitors();
+        if !declared.is_empty() && declared.iter().all(|m| m.width > 0 && m.height > 0) {
+            return Ok(declared);
+        }
+        let stale = match self.detected_at {
+            Some(at) => at.elapsed() >= MonitorSet::DETECT_EVERY,
+            None => true,
+        };
+        if stale {
+            self.detected = detect_monitors()?;
+            self.detected_at = Some(Instant::now());
+        }
+        let mut layout = self.detected.clone();
+        for (screen, m) in layout.iter_mut().zip(declared.iter()) {
+            screen.movie_path = m.movie_path.clone();
+            screen.frame_count = m.frame_count;
+            if m.width > 0 && m.height > 0 {
+                screen.x = m.x;
+                screen.y = m.y;
+                screen.width = m.width;
+                screen.height = m.height;
+            }
+        }
+        Ok(layout)
+    }
+
+    // Render the next frame of a screen's own movie to `picture_path` and move it on,
+    // looping at the end. Returns the frame that was rendered.
+    fn render_screen(
+        &mut self,
+        conf: &Config,
+        monitor: &Monitor,
+        picture_path: &str,
+    ) -> Result<u64, Errors> {
+        if !self.sources.contains_key(&monitor.movie_path) {
+            let mut source = open_source(
+                &monitor.movie_path,
+                &conf.get_image_pattern(),
+                conf.get_decode_backend(),
+            )?;
+            let total_frame = source.frame_count()?;
+            self.sources
+                .insert(monitor.movie_path.clone(), (source, total_frame));
+        }
+        let (source, total_frame) = match self.sources.get_mut(&monitor.movie_path) {
+            Some((source, total)) => (source, *total),
+            None => return Err(Errors::MonitorError(String::from("movie is not open"))),
+        };
+        let frame = monitor.frame_count.min(total_frame.saturating_sub(1));
+        let info = FrameInfo::from_source(
+            conf,
+            source.as_mut(),
+            &monitor.movie_path,
+            frame,
+            total_frame,
+        );
+        source.extract_frame(frame, picture_path)?;
+        finish_frame(picture_path, conf, &info)?;
+        Ok(frame)
+    }
+
+    /// Lay the rendered main frame at `picture_path` out over the whole desktop. The
+    /// frame is left as it is, the composite goes to a file of its own whose path is
+    /// returned, None in single mode where the frame is the wallpaper. Per-screen movies
+    /// move on unless `advance` is false; their progress is written back to the config,
+    /// which the caller saves.
+    pub fn compose(
+        &mut self,
+        conf: &mut Config,
+        picture_path: &str,
+        advance: bool,
+    ) -> Result<Option<String>, Errors> {
+        if conf.get_monitor_mode() == MonitorMode::Single {
+            return Ok(None);
+        }
+        let layout = self.layout(conf)?;
+        let main = image::open(picture_path)?;
+        let composed = match conf.get_monitor_mode() {
+            MonitorMode::Single => return Ok(None),
+            MonitorMode::Span => compose_span(&main, &layout, conf.get_monitor_bezel()),
+            MonitorMode::PerMonitor => {
+                let mut declared = conf.get_monitors();
+                let mut pictures: Vec<(&Monitor, DynamicImage)> = Vec::new();
+                for (i, monitor) in layout.iter().enumerate() {
+                    if monitor.movie_path.is_empty() {
+                        pictures.push((monitor, main.clone()));
+                        continue;
+                    }
+                    let screen_path = conf
+                        .state_file(&format!("monitor_{}.png", i))
+                        .display()
+                        .to_string();
+                    let frame = self.render_screen(conf, monitor, &screen_path)?;
+                    pictures.push((monitor, image::open(&screen_path)?));
+                    if let (true, Some(m)) = (advance, declared.get_mut(i)) {
+                        let total_frame = self
+                            .sources
+                            .get(&monitor.movie_path)
+                            .map(|(_, total)| *total)
+                            .unwrap_or(0);
+                        m.frame_count = match frame + 1 >= total_frame {
+                            true => 0,
+                            false => frame + 1,
+                        };
+                    }
+                }
+                // movies no screen plays any more are closed
+                let playing: Vec<String> = layout.iter().map(|m| m.movie_path.clone()).collect();
+                self.sources.retain(|path, _| playing.contains(path));
+                conf.set_monitors(declared);
+                compose_monitors(&pictures)
+            }
+        };
+        // Windows may keep the old picture when the wallpaper path does not change, so two
+        // files take turns
+        self.flip = !self.flip;
+        let desktop_path = conf
+            .state_file(&format!("desktop_{}.png", self.flip as u8))
+            .display()
+            .to_string();
+        composed.save_with_format(&desktop_path, image::ImageFormat::Png)?;
+        Ok(Some(desktop_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(x: i32, y: i32, width: u32, height: u32) -> Monitor {
+        Monitor {
+            x,
+            y,
+            width,
+            height,
+            ..Monitor::default()
+        }
+    }
+
+    // left half red, right half blue
+    fn halves(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, _| {
+            match x < width / 2 {
+                true => Rgb([255, 0, 0]),
+                false => Rgb([0, 0, 255]),
+            }
+        }))
+    }
+
+    #[test]
+    fn compose_span_splits_the_picture() {
+        let monitors = [monitor(0, 0, 100, 50), monitor(100, 0, 100, 50)];
+        let canvas = compose_span(&halves(200, 50), &monitors, [0, 0]);
+        assert_eq!(canvas.dimensions(), (200, 50));
+        assert!(canvas.get_pixel(10, 25)[0] > 200);
+        assert!(canvas.get_pixel(190, 25)[2] > 200);
+    }
+
+    #[test]
+    fn compose_span_hides_the_bezel() {
+        // the wall is 220 wide, the right screen starts past the middle of the picture
+        let monitors = [monitor(0, 0, 100, 50), monitor(100, 0, 100, 50)];
+        let canvas = compose_span(&halves(220, 50), &monitors, [20, 0]);
+        assert_eq!(canvas.dimensions(), (200, 50));
+        assert!(canvas.get_pixel(99, 25)[0] > 200);
+        assert!(canvas.get_pixel(100, 25)[2] > 200);
+    }
+
+    #[test]
+    fn compose_span_leaves_uncovered_desktop_black() {
+        let monitors = [monitor(0, 0, 100, 50), monitor(100, 25, 100, 50)];
+        let canvas = compose_span(&halves(200, 75), &monitors, [0, 0]);
+        assert_eq!(canvas.dimensions(), (200, 75));
+        assert_eq!(*canvas.get_pixel(10, 60), Rgb([0, 0, 0]));
+        assert_eq!(*canvas.get_pixel(150, 10), Rgb([0, 0, 0]));
+    }
+}
@@ -0,0 +1,309 @@
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+use rusttype::{Font, Scale};
+use serde::{Deserialize, Serialize};
+
+use super::errors::*;
+use super::render::FrameInfo;
+
+/// Where on the frame an overlay sits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    #[default]
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Top left corner for a `width` x `height` block placed `margin` pixels in from the edges.
+    pub fn place(&self, canvas: (u32, u32), block: (u32, u32), margin: u32) -> (i32, i32) {
+        let (cw, ch) = (canvas.0 as i32, canvas.1 as i32);
+        let (w, h) = (block.0 as i32, block.1 as i32);
+        let m = margin as i32;
+        let x = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => m,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => (cw - w) / 2,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => cw - w - m,
+        };
+        let y = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => m,
+            Anchor::Left | Anchor::Center | Anchor::Right => (ch - h) / 2,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => ch - h - m,
+        };
+        (x, y)
+    }
+}
+
+/// Look of a block of overlay text, colours are RGBA.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TextStyle {
+    pub font_path: String,
+    pub size: f32,
+    pub color: [u8; 4],
+    pub outline_color: [u8; 4],
+    pub outline_width: u32,
+    /// Box drawn behind the text, alpha 0 leaves it out.
+    pub box_color: [u8; 4],
+    pub box_padding: u32,
+    pub anchor: Anchor,
+    pub margin: u32,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            font_path: String::from(r"C:\Windows\Fonts\arial.ttf"),
+            size: 36.0,
+            color: [255, 255, 255, 255],
+            outline_color: [0, 0, 0, 255],
+            outline_width: 2,
+            box_color: [0, 0, 0, 0],
+            box_padding: 8,
+            anchor: Anchor::Bottom,
+            margin: 40,
+        }
+    }
+}
+
+pub fn load_font(font_path: &str) -> Result<Font<'static>, Errors> {
+    let data = std::fs::read(font_path)?;
+    match Font::try_from_vec(data) {
+        Some(font) => Ok(font),
+        None => Err(Errors::OverlayError(format!(
+            "{} is not a usable font",
+            font_path
+        ))),
+    }
+}
+
+/// Mix `color` over the pixel with the colour's own alpha times `coverage`.
+pub fn blend_pixel(picture: &mut RgbaImage, x: i32, y: i32, color: [u8; 4], coverage: f32) {
+    if x < 0 || y < 0 || x >= picture.width() as i32 || y >= picture.height() as i32 {
+        return;
+    }
+    let alpha = color[3] as f32 / 255.0 * coverage;
+    let pixel = picture.get_pixel_mut(x as u32, y as u32);
+    for c in 0..3 {
+        pixel[c] = (pixel[c] as f32 * (1.0 - alpha) + color[c] as f32 * alpha).round() as u8;
+    }
+}
+
+pub fn fill_rect(picture: &mut RgbaImage, x: i32, y: i32, width: u32, height: u32, color: [u8; 4]) {
+    if color[3] == 0 {
+        return;
+    }
+    for dy in 0..height as i32 {
+        for dx in 0..width as i32 {
+            blend_pixel(picture, x + dx, y + dy, color, 1.0);
+        }
+    }
+}
+
+/// Draw lines of text as one centred block at the style's anchor.
+pub fn draw_text_block(picture: &mut RgbaImage, lines: &[String], style: &TextStyle, font: &Font) {
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return;
+    }
+    let scale = Scale::uniform(style.size);
+    let line_height = (style.size * 1.2).ceil() as u32;
+    let widths: Vec<u32> = lines
+        .iter()
+        .map(|l| text_size(scale, font, l).0.max(0) as u32)
+        .collect();
+    let pad = style.box_padding + style.outline_width;
+    let block_width = widths.iter().copied().max().unwrap_or(0) + pad * 2;
+    let block_height = line_height * lines.len() as u32 + pad * 2;
+    let (x, y) = style.anchor.place(
+        (picture.width(), picture.height()),
+        (block_width, block_height),
+        style.margin,
+    );
+
+    fill_rect(picture, x, y, block_width, block_height, style.box_color);
+
+    let outline = Rgba(style.outline_color);
+    let color = Rgba(style.color);
+    let ow = style.outline_width as i32;
+    for (i, line) in lines.iter().enumerate() {
+        let lx = x + ((block_width - widths[i]) / 2) as i32;
+        let ly = y + pad as i32 + (line_height * i as u32) as i32;
+        if ow > 0 && style.outline_color[3] > 0 {
+            for dy in -ow..=ow {
+                for dx in -ow..=ow {
+                    if dx != 0 || dy != 0 {
+                        draw_text_mut(picture, outline, lx + dx, ly + dy, scale, font, line);
+                    }
+                }
+            }
+        }
+        draw_text_mut(picture, color, lx, ly, scale, font, line);
+    }
+}
+
+/// A line of templated text drawn on every frame.
+/// Placeholders: {title} {timecode} {duration} {percent} {frame} {total_frames} {time} {date}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TextOverlay {
+    pub template: String,
+    pub style: TextStyle,
+}
+
+impl Default for TextOverlay {
+    fn default() -> Self {
+        TextOverlay {
+            template: String::from("{title}  {timecode} / {duration}"),
+            style: TextStyle {
+                size: 24.0,
+                anchor: Anchor::TopRight,
+                margin: 20,
+                ..TextStyle::default()
+            },
+        }
+    }
+}
+
+pub fn format_timecode(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+pub fn movie_title(movie_path: &str) -> String {
+    match std::path::Path::new(movie_path).file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => movie_path.to_owned(),
+    }
+}
+
+pub fn expand_template(template: &str, info: &FrameInfo) -> String {
+    let now = chrono::Local::now();
+    let percent = match info.total_frame {
+        0 => 0.0,
+        n => info.frame as f64 * 100.0 / n as f64,
+    };
+    template
+        .replace("{title}", &movie_title(&info.movie_path))
+        .replace("{timecode}", &format_timecode(info.time_ms))
+        .replace("{duration}", &format_timecode(info.duration_ms))
+        .replace("{percent}", &format!("{:.1}%", percent))
+        .replace("{frame}", &info.frame.to_string())
+        .replace("{total_frames}", &info.total_frame.to_string())
+        .replace("{time}", &now.format("%H:%M").to_string())
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+}
+
+pub fn draw_text_overlays(
+    picture: &mut RgbaImage,
+    overlays: &[TextOverlay],
+    info: &FrameInfo,
+) -> Result<(), Errors> {
+    for overlay in overlays {
+        let font = load_font(&overlay.style.font_path)?;
+        let text = expand_template(&overlay.template, info);
+        let lines: Vec<String> = text.lines().map(|l| l.to_owned()).collect();
+        draw_text_block(picture, &lines, &overlay.style, &font);
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BarEdge {
+    Top,
+    #[default]
+    Bottom,
+}
+
+/// Thin bar along an edge showing how far into the movie we are, with chapter ticks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ProgressBarStyle {
+    pub enabled: bool,
+    pub edge: BarEdge,
+    pub thickness: u32,
+    pub color: [u8; 4],
+    pub track_color: [u8; 4],
+    pub tick_color: [u8; 4],
+    /// Multiplies the alpha of every colour above, 0.0 to 1.0.
+    pub opacity: f32,
+}
+
+impl Default for ProgressBarStyle {
+    fn default() -> Self {
+        ProgressBarStyle {
+            enabled: false,
+            edge: BarEdge::Bottom,
+            thickness: 6,
+            color: [255, 255, 255, 255],
+            track_color: [0, 0, 0, 160],
+            tick_color: [255, 200, 0, 255],
+            opacity: 0.8,
+        }
+    }
+}
+
+fn faded(color: [u8; 4], opacity: f32) -> [u8; 4] {
+    let alpha = (color[3] as f32 * opacity.clamp(0.0, 1.0)).round() as u8;
+    [color[0], color[1], color[2], alpha]
+}
+
+pub fn draw_progress_bar(picture: &mut RgbaImage, style: &ProgressBarStyle, info: &FrameInfo) {
+    let (width, height) = (picture.width(), picture.height());
+    let thickness = style.thickness.min(height);
+    if thickness == 0 || width == 0 {
+        return;
+    }
+    let y = match style.edge {
+        BarEdge::Top => 0,
+        BarEdge::Bottom => (height - thickness) as i32,
+    };
+    fill_rect(
+        picture,
+        0,
+        y,
+        width,
+        thickness,
+        faded(style.track_color, style.opacity),
+    );
+
+    let done = match info.total_frame {
+        0 => 0,
+        n => (info.frame.min(n) * width as u64 / n) as u32,
+    };
+    fill_rect(
+        picture,
+        0,
+        y,
+        done,
+        thickness,
+        faded(style.color, style.opacity),
+    );
+
+    if info.duration_ms == 0 {
+        return;
+    }
+    let tick_width = (thickness / 3).clamp(1, 3);
+    for chapter in info.chapters.iter().filter(|c| c.start_ms > 0) {
+        let x = (chapter.start_ms.min(info.duration_ms) * width as u64 / info.duration_ms) as i32;
+        fill_rect(
+            picture,
+            x - (tick_width / 2) as i32,
+            y,
+            tick_width,
+            thickness,
+            faded(style.tick_color, style.opacity),
+        );
+    }
+}
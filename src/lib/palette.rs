@@ -0,0 +1,176 @@
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+use super::config::Config;
+use super::errors::*;
+
+/// Colours in the pywal colors.json layout, so tools that read pywal themes can use it as is.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Palette {
+    pub wallpaper: String,
+    pub alpha: String,
+    pub special: BTreeMap<String, String>,
+    pub colors: BTreeMap<String, String>,
+    /// The colours as the frame has them, darkest first.
+    pub dominant: Vec<String>,
+}
+
+fn luminance(c: &[u8; 3]) -> u32 {
+    c[0] as u32 * 299 + c[1] as u32 * 587 + c[2] as u32 * 114
+}
+
+fn hex(c: &[u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])
+}
+
+// Move a colour `amount` of the way towards white, or towards black for a negative amount.
+fn shade(c: &[u8; 3], amount: f32) -> [u8; 3] {
+    let target = if amount < 0.0 { 0.0 } else { 255.0 };
+    let amount = amount.abs().min(1.0);
+    let mix = |v: u8| (v as f32 + (target - v as f32) * amount).round() as u8;
+    [mix(c[0]), mix(c[1]), mix(c[2])]
+}
+
+fn average(pixels: &[[u8; 3]]) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    for p in pixels {
+        for (s, v) in sum.iter_mut().zip(p.iter()) {
+            *s += *v as u64;
+        }
+    }
+    let n = pixels.len().max(1) as u64;
+    [(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8]
+}
+
+/// Dominant colours by median cut: keep splitting the box with the widest channel
+/// at its median until there are `count` boxes, then take each box's average. Darkest first.
+pub fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![pixels];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let (channel, range) = (0..3)
+                    .map(|c| {
+                        let low = b.iter().map(|p| p[c]).min().unwrap_or(0);
+                        let high = b.iter().map(|p| p[c]).max().unwrap_or(0);
+                        (c, high - low)
+                    })
+                    .max_by_key(|(_, range)| *range)
+                    .unwrap_or((0, 0));
+                (i, channel, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let (index, channel) = match widest {
+            Some((i, c, range)) if range > 0 => (i, c),
+            _ => break,
+        };
+        let mut lower = boxes.swap_remove(index);
+        lower.sort_by_key(|p| p[channel]);
+        let upper = lower.split_off(lower.len() / 2);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+    let mut colors: Vec<[u8; 3]> = boxes
+        .iter()
+        .filter(|b| !b.is_empty())
+        .map(|b| average(b))
+        .collect();
+    colors.sort_by_key(luminance);
+    colors
+}
+
+/// Palette of a picture. color0 is a darkened darkest colour for backgrounds,
+/// color7 the lightest for text, and 8 to 15 repeat them a little lighter, as pywal does.
+pub fn extract_palette(picture_path: &str) -> Result<Palette, Errors> {
+    let small = image::open(picture_path)?
+        .resize(128, 128, FilterType::Triangle)
+        .to_rgb8();
+    let pixels: Vec<[u8; 3]> = small.pixels().map(|p| p.0).collect();
+    let mut dominant = median_cut(pixels, 8);
+    if dominant.is_empty() {
+        return Err(Errors::PaletteError(String::from("picture has no pixels")));
+    }
+    while dominant.len() < 8 {
+        let last = dominant[dominant.len() - 1];
+        dominant.push(shade(&last, 0.2));
+    }
+
+    let mut base = dominant.clone();
+    base[0] = shade(&dominant[0], -0.6);
+    base[7] = shade(&dominant[7], 0.6);
+    let mut colors = BTreeMap::new();
+    for (i, c) in base.iter().enumerate() {
+        colors.insert(format!("color{}", i), hex(c));
+        let light = match i {
+            0 => shade(c, 0.25),
+            _ => shade(c, 0.15),
+        };
+        colors.insert(format!("color{}", i + 8), hex(&light));
+    }
+    let mut special = BTreeMap::new();
+    special.insert(String::from("background"), hex(&base[0]));
+    special.insert(String::from("foreground"), hex(&base[7]));
+    special.insert(String::from("cursor"), hex(&base[7]));
+
+    Ok(Palette {
+        wallpaper: picture_path.to_owned(),
+        alpha: String::from("100"),
+        special,
+        colors,
+        dominant: dominant.iter().map(hex).collect(),
+    })
+}
+
+/// Write palette.json and a pywal style `colors` file, one hex colour per line, next to the config.
+pub fn write_palette(conf: &Config, palette: &Palette) -> Result<(), Errors> {
+    let text =
+        serde_json::to_string_pretty(palette).map_err(|e| Errors::PaletteError(e.to_string()))?;
+    fs::write(conf.state_file("palette.json"), text)?;
+    let lines: Vec<String> = (0..16)
+        .map(|i| {
+            palette
+                .colors
+                .get(&format!("color{}", i))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect();
+    fs::write(conf.state_file("colors"), lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Run the palette hook with the palette file paths in SLOWMOVIE_PALETTE and SLOWMOVIE_COLORS.
+pub fn notify_palette(command: &str, conf: &Config) -> Result<(), Errors> {
+    if command.trim().is_empty() {
+        return Ok(());
+    }
+    log::info!("Palette command : {}", command);
+    if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", command])
+            .env("SLOWMOVIE_PALETTE", conf.state_file("palette.json"))
+            .env("SLOWMOVIE_COLORS", conf.state_file("colors"))
+            .creation_flags(0x08000000)
+            .spawn()?;
+    } else {
+        return Err(Errors::OSTypeError);
+    }
+    Ok(())
+}
+
+/// Update the palette files for a newly shown picture and tell the hook.
+pub fn update_palette(conf: &Config, picture_path: &Path) -> Result<(), Errors> {
+    let palette = extract_palette(&picture_path.display().to_string())?;
+    write_palette(conf, &palette)?;
+    notify_palette(&conf.get_palette_hook(), conf)
+}
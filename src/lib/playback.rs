@@ -0,0 +1,197 @@
+use super::errors::*;
+
+use std::os::windows::process::CommandExt;
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EndPolicy {
+    #[default]
+    Loop,
+    Hold,
+    Stop,
+    PingPong,
+    NextMovie,
+}
+impl EndPolicy {
+    pub const ALL: [EndPolicy; 5] = [
+        EndPolicy::Loop,
+        EndPolicy::Hold,
+        EndPolicy::Stop,
+        EndPolicy::PingPong,
+        EndPolicy::NextMovie,
+    ];
+}
+impl std::fmt::Display for EndPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                EndPolicy::Loop => "Loop",
+                EndPolicy::Hold => "Hold last frame",
+                EndPolicy::Stop => "Stop",
+                EndPolicy::PingPong => "Ping-pong",
+                EndPolicy::NextMovie => "Next movie",
+            }
+        )
+    }
+}
+
+/// Where playback goes after the frame at `position` has been shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    pub next_frame: u64,
+    pub reverse: bool,
+    /// The last frame of the movie was just reached.
+    pub ended: bool,
+    pub stop: bool,
+    pub next_movie: bool,
+}
+
+/// Compute the next frame for interval playback of the frames in [first_frame, end_frame).
+/// A position at or past `end_frame` means the last frame is being held.
+pub fn advance_in(
+    position: u64,
+    first_frame: u64,
+    end_frame: u64,
+    reverse: bool,
+    policy: EndPolicy,
+) -> Step {
+    let mut step = Step {
+        next_frame: position,
+        reverse,
+        ended: false,
+        stop: false,
+        next_movie: false,
+    };
+    if end_frame <= first_frame {
+        step.next_frame = first_frame;
+        return step;
+    }
+    let last_frame = end_frame - 1;
+
+    if reverse {
+        if position <= first_frame || position > last_frame {
+            step.reverse = false;
+            step.next_frame = (first_frame + 1).min(last_frame);
+        } else {
+            step.next_frame = position - 1;
+        }
+        return step;
+    }
+
+    if position < first_frame {
+        step.next_frame = first_frame;
+        return step;
+    }
+    if position < last_frame {
+        step.next_frame = position + 1;
+        return step;
+    }
+    if position > last_frame {
+        // already holding the last frame
+        return step;
+    }
+
+    step.ended = true;
+    match policy {
+        EndPolicy::Loop => step.next_frame = first_frame,
+        EndPolicy::Hold => step.next_frame = end_frame,
+        EndPolicy::Stop => step.stop = true,
+        EndPolicy::PingPong => {
+            step.reverse = true;
+            step.next_frame = last_frame.saturating_sub(1).max(first_frame);
+        }
+        EndPolicy::NextMovie => {
+            step.next_frame = first_frame;
+            step.next_movie = true;
+        }
+    }
+    step
+}
+
+/// Run the user's end-of-movie command, the finished movie is passed in SLOWMOVIE_MOVIE.
+pub fn notify_movie_end(command: &str, movie_path: &str) -> Result<(), Errors> {
+    if command.trim().is_empty() {
+        return Ok(());
+    }
+    log::info!("Movie end command : {}", command);
+    if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", command])
+            .env("SLOWMOVIE_MOVIE", movie_path)
+            .creation_flags(0x08000000)
+            .spawn()?;
+    } else {
+        return Err(Errors::OSTypeError);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // frames 10 to 19 of the movie are played
+    fn step(position: u64, reverse: bool, policy: EndPolicy) -> Step {
+        advance_in(position, 10, 20, reverse, policy)
+    }
+
+    #[test]
+    fn advance_in_moves_one_frame() {
+        for policy in EndPolicy::ALL {
+            let forward = step(12, false, policy);
+            assert_eq!((forward.next_frame, forward.reverse), (13, false));
+            assert!(!forward.ended && !forward.stop && !forward.next_movie);
+            let back = step(12, true, policy);
+            assert_eq!((back.next_frame, back.reverse), (11, true));
+            assert!(!back.ended && !back.stop && !back.next_movie);
+            // a position before the range starts at its first frame
+            assert_eq!(step(3, false, policy).next_frame, 10);
+        }
+    }
+
+    #[test]
+    fn advance_in_applies_the_end_policy() {
+        let end = |policy| step(19, false, policy);
+        let looped = end(EndPolicy::Loop);
+        assert_eq!((looped.next_frame, looped.ended), (10, true));
+        assert!(!looped.stop && !looped.next_movie);
+
+        let held = end(EndPolicy::Hold);
+        assert_eq!((held.next_frame, held.ended), (20, true));
+        // holding does not end the movie again
+        let holding = step(20, false, EndPolicy::Hold);
+        assert_eq!((holding.next_frame, holding.ended), (20, false));
+
+        let stopped = end(EndPolicy::Stop);
+        assert!(stopped.ended && stopped.stop && !stopped.next_movie);
+        assert_eq!(stopped.next_frame, 19);
+
+        let bounced = end(EndPolicy::PingPong);
+        assert_eq!((bounced.next_frame, bounced.reverse), (18, true));
+        assert!(bounced.ended && !bounced.stop);
+
+        let next = end(EndPolicy::NextMovie);
+        assert_eq!(next.next_frame, 10);
+        assert!(next.ended && next.next_movie && !next.stop);
+    }
+
+    #[test]
+    fn advance_in_turns_at_the_first_frame_in_reverse() {
+        // playing backwards only happens in ping-pong, the start turns it forward for any policy
+        for policy in EndPolicy::ALL {
+            let turned = step(10, true, policy);
+            assert_eq!((turned.next_frame, turned.reverse), (11, false));
+            assert!(!turned.ended && !turned.stop && !turned.next_movie);
+            // a held position past the end plays forward from the start again
+            assert_eq!(step(20, true, policy).next_frame, 11);
+        }
+    }
+
+    #[test]
+    fn advance_in_empty_range() {
+        let empty = advance_in(5, 10, 10, false, EndPolicy::Loop);
+        assert_eq!((empty.next_frame, empty.ended), (10, false));
+    }
+}
@@ -0,0 +1,262 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+
+use super::config::Config;
+use super::errors::*;
+use super::watch_folder::FolderWatcher;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PlaylistItem {
+    pub path: String,
+    pub start_frame: u64,
+    /// 0 plays to the end of the movie.
+    pub end_frame: u64,
+    pub progress: u64,
+}
+
+impl PlaylistItem {
+    pub fn new(path: String) -> PlaylistItem {
+        PlaylistItem {
+            path,
+            ..PlaylistItem::default()
+        }
+    }
+
+    /// Frames this item plays, as a half open range [first, end).
+    pub fn frame_range(&self, total_frame: u64) -> (u64, u64) {
+        let end = match self.end_frame {
+            0 => total_frame,
+            n => n.min(total_frame),
+        };
+        (self.start_frame.min(end.saturating_sub(1)), end)
+    }
+}
+
+pub(crate) fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    match path.extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy().to_lowercase();
+            extensions.contains(&ext)
+        }
+        None => false,
+    }
+}
+
+fn collect_movies(
+    dir: &Path,
+    recursive: bool,
+    extensions: &[String],
+    found: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                collect_movies(&path, recursive, extensions, found)?;
+            }
+        } else if has_extension(&path, extensions) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Split a comma separated list like "mp4,.MKV" into lower case extensions.
+pub(crate) fn parse_extensions(extensions: &str) -> Vec<String> {
+    extensions
+        .split(',')
+        .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+/// List movie files in a folder, `extensions` is a comma separated list like "mp4,mkv".
+pub fn scan_folder(dir: &str, recursive: bool, extensions: &str) -> Result<Vec<String>, Errors> {
+    let extensions = parse_extensions(extensions);
+    let mut found = Vec::new();
+    if let Err(e) = collect_movies(Path::new(dir), recursive, &extensions, &mut found) {
+        return Err(Errors::PlaylistError(format!(
+            "Scan folder {} failed. {}",
+            dir, e
+        )));
+    }
+    found.sort();
+    Ok(found.iter().map(|p| p.display().to_string()).collect())
+}
+
+/// Rebuild a folder playlist from disk, keeping the progress of movies that are still there.
+/// With a watcher running the list comes from the movies it has already probed.
+pub fn refresh_folder(conf: &mut Config, watcher: Option<&FolderWatcher>) -> Result<(), Errors> {
+    let folder = conf.get_playlist_folder();
+    if folder.is_empty() {
+        return Ok(());
+    }
+    let found = match watcher {
+        Some(w) => match w.movies() {
+            Some(movies) => movies,
+            // first look at the folder is not finished yet
+            None => return Ok(()),
+        },
+        None => scan_folder(
+            &folder,
+            conf.is_playlist_recursive(),
+            &conf.get_playlist_extensions(),
+        )?,
+    };
+    let old = conf.get_playlist();
+    let items: Vec<PlaylistItem> = found
+        .into_iter()
+        .map(|path| match old.iter().find(|item| item.path == path) {
+            Some(item) => item.clone(),
+            None => PlaylistItem::new(path),
+        })
+        .collect();
+    if items == old {
+        return Ok(());
+    }
+    log::info!("Playlist folder changed, {} movies now.", items.len());
+
+    let current = conf.current_playlist_item().map(|item| item.path);
+    let index = current.and_then(|path| items.iter().position(|item| item.path == path));
+    conf.set_playlist(items);
+    match index {
+        Some(i) => conf.set_playlist_index(i),
+        None => conf.select_playlist_item(0),
+    }
+    Ok(())
+}
+
+/// Make the config point at the current playlist movie before a tick.
+/// A movie picked explicitly, from the settings or a jump, is followed where it is in the
+/// list instead of being replaced by the remembered entry.
+pub fn prepare(conf: &mut Config, watcher: Option<&FolderWatcher>) -> Result<(), Errors> {
+    refresh_folder(conf, watcher)?;
+    let playlist = conf.get_playlist();
+    if playlist.is_empty() {
+        return Ok(());
+    }
+    let movie_path = conf.get_movie_path();
+    match playlist.iter().position(|item| item.path == movie_path) {
+        Some(i) if i == conf.get_playlist_index() => {}
+        Some(i) => {
+            let frame = conf.get_frame_count();
+            conf.set_playlist_index(i);
+            conf.set_frame_count(frame);
+        }
+        None => conf.select_playlist_item(conf.get_playlist_index()),
+    }
+    Ok(())
+}
+
+/// Pick the item after `current`, or None when the playlist is over and repeat is off.
+/// Shuffle draws from the movies not in `played` yet, so every movie comes once a round;
+/// with repeat on a new round starts once all of them were played.
+pub fn next_index(
+    len: usize,
+    current: usize,
+    shuffle: bool,
+    repeat: bool,
+    played: &[usize],
+) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    if shuffle {
+        let mut left: Vec<usize> = (0..len)
+            .filter(|i| *i != current && !played.contains(i))
+            .collect();
+        if left.is_empty() {
+            if !repeat {
+                return None;
+            }
+            // any movie but the one that just finished
+            left = (0..len).filter(|i| *i != current).collect();
+            if left.is_empty() {
+                return Some(current);
+            }
+        }
+        return Some(left[rand::thread_rng().gen_range(0..left.len())]);
+    }
+    if current + 1 < len {
+        Some(current + 1)
+    } else if repeat {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+/// Move on to the next movie after the current one finished.
+/// Returns false when there is nothing left to play.
+pub fn advance_playlist(conf: &mut Config) -> bool {
+    let playlist = conf.get_playlist();
+    if playlist.is_empty() {
+        return true;
+    }
+    let current = conf.get_playlist_index();
+    // a finished movie starts over next time it comes round
+    let mut played = conf.get_shuffle_played();
+    if let Some(item) = playlist.get(current) {
+        conf.set_frame_count(item.start_frame);
+        if !played.contains(&item.path) {
+            played.push(item.path.clone());
+        }
+    }
+    // movies that left the list no longer count
+    played.retain(|path| playlist.iter().any(|item| item.path == *path));
+    let played_index: Vec<usize> = (0..playlist.len())
+        .filter(|i| played.contains(&playlist[*i].path))
+        .collect();
+    match next_index(
+        playlist.len(),
+        current,
+        conf.is_shuffle(),
+        conf.is_repeat(),
+        &played_index,
+    ) {
+        Some(next) => {
+            // a movie played before means every one was, the next round begins
+            if played.contains(&playlist[next].path) {
+                played.clear();
+            }
+            conf.set_shuffle_played(played);
+            conf.select_playlist_item(next);
+            log::info!("Playlist moved to {}", conf.get_movie_path());
+            true
+        }
+        None => {
+            conf.set_shuffle_played(Vec::new());
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_index_in_order() {
+        assert_eq!(next_index(0, 0, false, true, &[]), None);
+        assert_eq!(next_index(3, 0, false, false, &[]), Some(1));
+        assert_eq!(next_index(3, 2, false, false, &[]), None);
+        assert_eq!(next_index(3, 2, false, true, &[]), Some(0));
+    }
+
+    #[test]
+    fn next_index_shuffle_finishes_a_round() {
+        // only the movie not played yet is left
+        assert_eq!(next_index(3, 0, true, false, &[0, 1]), Some(2));
+        assert_eq!(next_index(3, 2, true, false, &[0, 1, 2]), None);
+        assert_eq!(next_index(1, 0, true, false, &[0]), None);
+        assert_eq!(next_index(1, 0, true, true, &[0]), Some(0));
+        // a new round never starts with the movie that just finished
+        for _ in 0..20 {
+            let next = next_index(3, 1, true, true, &[0, 1, 2]);
+            assert!(matches!(next, Some(0) | Some(2)));
+        }
+    }
+}
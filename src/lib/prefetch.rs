@@ -0,0 +1,251 @@
+use chrono::{Duration as ChronoDuration, Local};
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::config::{Config, PlayMode};
+use super::errors::*;
+use super::film_clock::{daily_frame, parse_day_time};
+use super::playback::{advance_in, EndPolicy};
+use super::source::{open_source, DecodeBackend, FrameSource};
+use super::utils::delete_file;
+
+/// Short stable name for a movie, used for file names under the cache folders.
+pub(crate) fn movie_key(movie_path: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    movie_path.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Frames the wallpaper loop is going to ask for next.
+#[derive(Debug, Clone)]
+pub struct PrefetchJob {
+    pub movie_path: String,
+    pub image_pattern: String,
+    pub backend: DecodeBackend,
+    pub frames: Vec<u64>,
+}
+
+/// Extracted frames on disk, one file per movie and frame.
+/// Output processing is not cached since overlays like {time} change between ticks.
+#[derive(Debug, Clone)]
+pub struct FrameCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+}
+
+impl FrameCache {
+    pub fn new(dir: PathBuf, max_bytes: u64, max_age: Duration) -> Result<FrameCache, Errors> {
+        fs::create_dir_all(&dir)?;
+        Ok(FrameCache {
+            dir,
+            max_bytes,
+            max_age,
+        })
+    }
+
+    fn path_of(&self, movie_path: &str, frame: u64) -> PathBuf {
+        self.dir
+            .join(format!("{}_{}.png", movie_key(movie_path), frame))
+    }
+
+    pub fn contains(&self, movie_path: &str, frame: u64) -> bool {
+        self.path_of(movie_path, frame).is_file()
+    }
+
+    /// Move a cached frame to `picture_path`. Returns false on a miss.
+    pub fn take(&self, movie_path: &str, frame: u64, picture_path: &str) -> bool {
+        let cached = self.path_of(movie_path, frame);
+        if !cached.is_file() {
+            return false;
+        }
+        match fs::rename(&cached, picture_path) {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("Take cached frame {} error! Error:{}", frame, e);
+                false
+            }
+        }
+    }
+
+    fn store(
+        &self,
+        source: &mut dyn FrameSource,
+        movie_path: &str,
+        frame: u64,
+    ) -> Result<(), Errors> {
+        let target = self.path_of(movie_path, frame);
+        // written aside and renamed, so a half written file is never taken
+        let partial = target.with_extension("part.png");
+        // one left by an interrupted run would make ffmpeg refuse to write it again
+        let partial = partial.display().to_string();
+        delete_file(&partial)?;
+        source.extract_frame(frame, &partial)?;
+        fs::rename(&partial, &target)?;
+        Ok(())
+    }
+
+    /// Drop frames older than the age limit, then the oldest ones until the cache fits its size.
+    pub fn evict(&self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("Read frame cache error! Error:{}", e);
+                return;
+            }
+        };
+        let now = SystemTime::now();
+        let mut files: Vec<(SystemTime, u64, PathBuf)> = Vec::new();
+        for entry in entries.flatten() {
+            let meta = match entry.metadata() {
+                Ok(m) if m.is_file() => m,
+                _ => continue,
+            };
+            let modified = meta.modified().unwrap_or(now);
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age > self.max_age {
+                remove_cached(&entry.path());
+            } else {
+                files.push((modified, meta.len(), entry.path()));
+            }
+        }
+        let mut total: u64 = files.iter().map(|f| f.1).sum();
+        files.sort_by_key(|f| f.0);
+        for (_, size, path) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            remove_cached(&path);
+            total = total.saturating_sub(size);
+        }
+    }
+}
+
+fn remove_cached(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        log::warn!("Remove cached frame {} error! Error:{}", path.display(), e);
+    }
+}
+
+/// Renders upcoming frames into a [`FrameCache`] on a background thread,
+/// so a tick only has to move a finished file into place.
+pub struct Prefetcher {
+    cache: FrameCache,
+    // dropping the sender ends the background thread
+    jobs: Sender<PrefetchJob>,
+}
+
+impl Prefetcher {
+    pub fn start(cache: FrameCache) -> Prefetcher {
+        let (jobs, rx) = channel();
+        let worker_cache = cache.clone();
+        thread::spawn(move || prefetch_worker(worker_cache, rx));
+        Prefetcher { cache, jobs }
+    }
+
+    pub fn cache(&self) -> &FrameCache {
+        &self.cache
+    }
+
+    /// Replace whatever the worker was doing with a new list of frames.
+    pub fn request(&self, job: PrefetchJob) {
+        if let Err(e) = self.jobs.send(job) {
+            log::warn!("Prefetch worker is gone! Error:{}", e);
+        }
+    }
+}
+
+fn prefetch_worker(cache: FrameCache, rx: Receiver<PrefetchJob>) {
+    let mut opened: Option<(String, Box<dyn FrameSource>)> = None;
+    let mut next = rx.recv().ok();
+    while let Some(job) = next.take() {
+        let reuse = matches!(&opened, Some((path, _)) if *path == job.movie_path);
+        if !reuse {
+            opened = match open_source(&job.movie_path, &job.image_pattern, job.backend) {
+                Ok(source) => Some((job.movie_path.clone(), source)),
+                Err(e) => {
+                    log::warn!("Prefetch open {} error! Error:{}", job.movie_path, e);
+                    None
+                }
+            };
+        }
+        if let Some((_, source)) = opened.as_mut() {
+            for frame in job.frames.iter() {
+                // a newer job makes the rest of this one useless
+                match rx.try_recv() {
+                    Ok(newer) => {
+                        next = Some(newer);
+                        break;
+                    }
+                    Err(TryRecvError::Disconnected) => return,
+                    Err(TryRecvError::Empty) => {}
+                }
+                if cache.contains(&job.movie_path, *frame) {
+                    continue;
+                }
+                if let Err(e) = cache.store(source.as_mut(), &job.movie_path, *frame) {
+                    log::warn!("Prefetch frame {} error! Error:{}", frame, e);
+                    break;
+                }
+            }
+        }
+        cache.evict();
+        if next.is_none() {
+            next = rx.recv().ok();
+        }
+    }
+}
+
+/// The next `count` frames the wallpaper loop will show, starting with the one due next.
+pub fn upcoming_frames(
+    conf: &Config,
+    first_frame: u64,
+    end_frame: u64,
+    total_frame: u64,
+    count: usize,
+) -> Vec<u64> {
+    let mut frames = Vec::with_capacity(count);
+    match conf.get_play_mode() {
+        PlayMode::Interval => {
+            let policy = conf.get_end_policy();
+            let mut position = conf.get_frame_count();
+            let mut reverse = conf.is_reverse();
+            while frames.len() < count {
+                let frame =
+                    position.clamp(first_frame, end_frame.saturating_sub(1).max(first_frame));
+                frames.push(frame);
+                let step = advance_in(position, first_frame, end_frame, reverse, policy);
+                // past the end the next frame belongs to another movie or to nothing
+                if step.ended && policy != EndPolicy::Loop && policy != EndPolicy::PingPong {
+                    break;
+                }
+                position = step.next_frame;
+                reverse = step.reverse;
+            }
+        }
+        PlayMode::Daily => {
+            let (start, end) = match (
+                parse_day_time(&conf.get_day_start()),
+                parse_day_time(&conf.get_day_end()),
+            ) {
+                (Ok(s), Ok(e)) => (s, e),
+                _ => return frames,
+            };
+            let interval = ChronoDuration::seconds(i64::from(conf.get_time_interval()));
+            let now = Local::now();
+            for i in 1..=count as i32 {
+                let frame = daily_frame(now + interval * i, start, end, total_frame);
+                if frames.last() != Some(&frame) {
+                    frames.push(frame);
+                }
+            }
+        }
+    }
+    frames
+}
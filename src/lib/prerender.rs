@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::thread;
+use std::time::Duration;
+
+use super::config::Config;
+use super::errors::*;
+use super::prefetch::movie_key;
+use super::render::{finish_frame, FrameInfo};
+use super::source::{open_source, DecodeBackend};
+use super::video_process::{ffmpeg_exe, spawn_frame_range};
+
+/// Written next to the prerendered frames so the wallpaper loop knows how they map to the movie.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct PrerenderManifest {
+    pub movie_path: String,
+    pub step: u64,
+    pub total_frame: u64,
+}
+
+/// What a prerender run is going to do.
+#[derive(Debug, Clone)]
+pub struct PrerenderPlan {
+    pub movie_path: String,
+    pub step: u64,
+    pub total_frame: u64,
+    /// Pictures in the whole run, one per `step` frames.
+    pub count: u64,
+    /// Pictures already there from an earlier run.
+    pub done: u64,
+    /// Disk space the missing pictures will take, guessed from one sample frame.
+    pub estimated_bytes: u64,
+}
+
+pub fn prerender_dir(conf: &Config, movie_path: &str) -> PathBuf {
+    conf.state_file("prerender").join(movie_key(movie_path))
+}
+
+fn picture_name(index: u64) -> String {
+    format!("{:08}.png", index)
+}
+
+fn load_manifest(dir: &Path) -> Option<PrerenderManifest> {
+    let text = fs::read_to_string(dir.join("manifest.json")).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// The prerendered picture standing in for `frame`, the closest one at or before it.
+pub fn prerendered_frame(conf: &Config, movie_path: &str, frame: u64) -> Option<PathBuf> {
+    let dir = prerender_dir(conf, movie_path);
+    let manifest = load_manifest(&dir)?;
+    if manifest.step == 0 || manifest.movie_path != movie_path {
+        return None;
+    }
+    let path = dir.join(picture_name(frame / manifest.step));
+    match path.is_file() {
+        true => Some(path),
+        false => None,
+    }
+}
+
+/// Work out what prerendering `movie_path` at `step` involves. Pictures left by an
+/// earlier run with the same step are kept, so an interrupted run picks up where it stopped.
+pub fn plan_prerender(conf: &Config, movie_path: &str, step: u64) -> Result<PrerenderPlan, Errors> {
+    if !Path::new(movie_path).is_file() {
+        return Err(Errors::PrerenderError(format!(
+            "{} is not a movie file",
+            movie_path
+        )));
+    }
+    let step = step.max(1);
+    let mut source = open_source(movie_path, "", DecodeBackend::Cli)?;
+    let total_frame = source.frame_count()?;
+    let count = total_frame.div_ceil(step);
+
+    let dir = prerender_dir(conf, movie_path);
+    fs::create_dir_all(&dir)?;
+    let done = match load_manifest(&dir) {
+        Some(m) if m.step == step && m.total_frame == total_frame => (0..count)
+            .find(|i| !dir.join(picture_name(*i)).is_file())
+            .unwrap_or(count),
+        _ => 0,
+    };
+
+    // one finished frame from the middle tells how big the rest will be
+    let sample_frame = total_frame / 2;
+    let sample = dir.join("sample.png");
+    let sample_path = sample.display().to_string();
+    let info = FrameInfo::from_source(conf, source.as_mut(), movie_path, sample_frame, total_frame);
+    source.extract_frame(sample_frame, &sample_path)?;
+    finish_frame(&sample_path, conf, &info)?;
+    let sample_bytes = fs::metadata(&sample)?.len();
+    fs::remove_file(&sample)?;
+
+    Ok(PrerenderPlan {
+        movie_path: movie_path.to_owned(),
+        step,
+        total_frame,
+        count,
+        done,
+        estimated_bytes: sample_bytes * (count - done),
+    })
+}
+
+/// Extract every `step`th frame in one ffmpeg pass and apply the output processing to each.
+/// `progress` is called with (done, count) after every picture.
+/// Overlays are drawn once here, so a {time} or {date} overlay shows when the frame was made.
+pub fn prerender(
+    conf: &Config,
+    plan: &PrerenderPlan,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<(), Errors> {
+    let dir = prerender_dir(conf, &plan.movie_path);
+    if plan.done == 0 {
+        // a run with another step leaves nothing worth keeping
+        for entry in fs::read_dir(&dir)?.flatten() {
+            if entry.path().is_file() {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+    let manifest = PrerenderManifest {
+        movie_path: plan.movie_path.clone(),
+        step: plan.step,
+        total_frame: plan.total_frame,
+    };
+    match serde_json::to_string(&manifest) {
+        Ok(text) => fs::write(dir.join("manifest.json"), text)?,
+        Err(e) => return Err(Errors::PrerenderError(e.to_string())),
+    }
+
+    let mut source = open_source(&plan.movie_path, "", DecodeBackend::Cli)?;
+    let mut done = plan.done;
+    progress(done, plan.count);
+    for_each_range_frame(
+        &plan.movie_path,
+        done * plan.step,
+        0,
+        plan.step,
+        done,
+        &dir.join("raw"),
+        |index, raw| {
+            let info = FrameInfo::from_source(
+                conf,
+                source.as_mut(),
+                &plan.movie_path,
+                index * plan.step,
+                plan.total_frame,
+            );
+            finish_frame(&raw.display().to_string(), conf, &info)?;
+            fs::rename(raw, dir.join(picture_name(index)))?;
+            done += 1;
+            progress(done, plan.count);
+            Ok(())
+        },
+    )
+}
+
+/// Run one ffmpeg pass over every `step`th frame from `first_frame` up to `end_frame`, 0 for
+/// the end of the movie, and hand each picture
+/// to `on_picture` with its number, counted from `start_number`, as soon as it is complete.
+/// The pictures are written to `staging`, which is emptied before and removed after.
+pub(crate) fn for_each_range_frame(
+    movie_path: &str,
+    first_frame: u64,
+    end_frame: u64,
+    step: u64,
+    start_number: u64,
+    staging: &Path,
+    mut on_picture: impl FnMut(u64, &Path) -> Result<(), Errors>,
+) -> Result<(), Errors> {
+    if staging.is_dir() {
+        fs::remove_dir_all(staging)?;
+    }
+    fs::create_dir_all(staging)?;
+
+    let mut child = spawn_frame_range(
+        &ffmpeg_exe()?,
+        movie_path,
+        first_frame,
+        end_frame,
+        step,
+        start_number,
+        &staging.join("%08d.png").display().to_string(),
+    )?;
+    // ffmpeg stops once the pipe is full, so its messages are read on the side
+    let messages = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            let _ = pipe.read_to_string(&mut text);
+            text
+        })
+    });
+
+    let polled = poll_range_frames(&mut child, staging, &mut on_picture);
+    if polled.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    let messages = messages
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default();
+    polled?;
+    fs::remove_dir_all(staging)?;
+    if !status.success() {
+        return Err(Errors::FfmpegRunError(messages));
+    }
+    Ok(())
+}
+
+// Hand pictures over as ffmpeg finishes them, until it exits.
+fn poll_range_frames(
+    child: &mut Child,
+    staging: &Path,
+    on_picture: &mut impl FnMut(u64, &Path) -> Result<(), Errors>,
+) -> Result<(), Errors> {
+    loop {
+        let finished = child.try_wait()?.is_some();
+        let mut ready: Vec<PathBuf> = fs::read_dir(staging)?.flatten().map(|e| e.path()).collect();
+        ready.sort();
+        // ffmpeg may still be writing the newest picture
+        if !finished {
+            ready.pop();
+        }
+        for picture in ready {
+            let index = match picture
+                .file_stem()
+                .and_then(|s| s.to_string_lossy().parse::<u64>().ok())
+            {
+                Some(i) => i,
+                None => continue,
+            };
+            on_picture(index, &picture)?;
+            if picture.is_file() {
+                fs::remove_file(&picture)?;
+            }
+        }
+        if finished {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+}
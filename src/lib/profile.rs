@@ -0,0 +1,83 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use super::errors::*;
+
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Select the profile for the rest of the process. Can only be done once, before the
+/// config is first loaded.
+pub fn set_profile(name: &str) -> Result<(), Errors> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Errors::ProfileError(format!(
+            "\"{}\" is not a profile name, use letters, digits, - and _",
+            name
+        )));
+    }
+    PROFILE
+        .set(name.to_owned())
+        .map_err(|_| Errors::ProfileError(String::from("profile is already selected")))
+}
+
+/// Name of the active profile, empty for the default one.
+pub fn profile_name() -> String {
+    PROFILE.get().cloned().unwrap_or_default()
+}
+
+/// Folder of the active profile's config and state. The default profile keeps them in the
+/// working folder as before, named ones under profiles\<name>.
+pub fn profile_dir() -> PathBuf {
+    let mut dir = env::current_dir().unwrap();
+    let name = profile_name();
+    if !name.is_empty() {
+        dir.push("profiles");
+        dir.push(name);
+    }
+    dir
+}
+
+/// Log folder of the active profile.
+pub fn log_dir() -> String {
+    match profile_name().is_empty() {
+        true => String::from("logs/"),
+        false => format!("profiles/{}/logs/", profile_name()),
+    }
+}
+
+/// Name of the lock that keeps one instance per profile.
+pub fn lock_name() -> String {
+    match profile_name().is_empty() {
+        true => String::from("slowmovie"),
+        false => format!("slowmovie-{}", profile_name()),
+    }
+}
+
+/// Take `--profile <name>` or `--profile=<name>` out of the command line.
+pub fn profile_args(args: Vec<String>) -> (Option<String>, Vec<String>) {
+    let mut profile = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            profile = args.next();
+        } else if let Some(name) = arg.strip_prefix("--profile=") {
+            profile = Some(name.to_owned());
+        } else {
+            rest.push(arg);
+        }
+    }
+    (profile, rest)
+}
+
+/// Command line that starts another program of this suite in the same profile.
+pub fn profile_command(exe: &str) -> String {
+    match profile_name().is_empty() {
+        true => exe.to_owned(),
+        false => format!("{} --profile {}", exe, profile_name()),
+    }
+}
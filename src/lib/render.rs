@@ -0,0 +1,102 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbaImage};
+
+use super::config::Config;
+use super::errors::*;
+use super::overlay::*;
+use super::source::FrameSource;
+use super::subtitle::*;
+use super::video_process::Chapter;
+
+/// What is being shown, handed to the overlays.
+#[derive(Debug, Clone, Default)]
+pub struct FrameInfo {
+    pub movie_path: String,
+    pub frame: u64,
+    pub total_frame: u64,
+    pub time_ms: u64,
+    pub duration_ms: u64,
+    pub chapters: Vec<Chapter>,
+}
+
+impl FrameInfo {
+    /// Info for `frame` with timing from the source, chapters only when the progress bar wants them.
+    pub fn from_source(
+        conf: &Config,
+        source: &mut dyn FrameSource,
+        movie_path: &str,
+        frame: u64,
+        total_frame: u64,
+    ) -> FrameInfo {
+        FrameInfo {
+            movie_path: movie_path.to_owned(),
+            frame,
+            total_frame,
+            time_ms: source.frame_time_ms(frame),
+            duration_ms: source.frame_time_ms(total_frame),
+            chapters: match conf.get_progress_bar().enabled {
+                true => source.chapters(),
+                false => Vec::new(),
+            },
+        }
+    }
+}
+
+/// Scale to the configured output size. With both sides set the frame fills the output
+/// and the overflow is cropped, with one side set the other follows the aspect ratio.
+pub fn fit_to_output(picture: DynamicImage, width: u32, height: u32) -> DynamicImage {
+    match (width, height) {
+        (0, 0) => picture,
+        (w, 0) => {
+            let h = (picture.height() as u64 * w as u64 / picture.width().max(1) as u64) as u32;
+            picture.resize_exact(w, h.max(1), FilterType::Lanczos3)
+        }
+        (0, h) => {
+            let w = (picture.width() as u64 * h as u64 / picture.height().max(1) as u64) as u32;
+            picture.resize_exact(w.max(1), h, FilterType::Lanczos3)
+        }
+        (w, h) => picture.resize_to_fill(w, h, FilterType::Lanczos3),
+    }
+}
+
+fn draw_subtitle(canvas: &mut RgbaImage, conf: &Config, info: &FrameInfo) -> Result<(), Errors> {
+    let cues = load_cues(conf, &info.movie_path)?;
+    if let Some(cue) = cue_at(&cues, info.time_ms) {
+        let style = conf.get_subtitle_style();
+        let font = load_font(&style.font_path)?;
+        draw_text_block(canvas, &cue.lines, &style, &font);
+    }
+    Ok(())
+}
+
+/// Output processing shared by every source, applied in place to the extracted frame.
+pub fn finish_frame(picture_path: &str, conf: &Config, info: &FrameInfo) -> Result<(), Errors> {
+    let (width, height) = (conf.get_output_width(), conf.get_output_height());
+    let text_overlays = conf.get_text_overlays();
+    let progress_bar = conf.get_progress_bar();
+    if width == 0
+        && height == 0
+        && !conf.is_subtitle_enabled()
+        && text_overlays.is_empty()
+        && !progress_bar.enabled
+    {
+        return Ok(());
+    }
+    let picture = image::open(picture_path)?;
+    let mut canvas = fit_to_output(picture, width, height).to_rgba8();
+
+    // a broken overlay should not cost the frame
+    if conf.is_subtitle_enabled() {
+        if let Err(e) = draw_subtitle(&mut canvas, conf, info) {
+            log::warn!("Draw subtitle error! Error:{}", e);
+        }
+    }
+    if let Err(e) = draw_text_overlays(&mut canvas, &text_overlays, info) {
+        log::warn!("Draw text overlay error! Error:{}", e);
+    }
+    if progress_bar.enabled {
+        draw_progress_bar(&mut canvas, &progress_bar, info);
+    }
+    canvas.save_with_format(picture_path, image::ImageFormat::Png)?;
+    Ok(())
+}
@@ -0,0 +1,333 @@
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, Frames};
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use super::errors::*;
+use super::playlist::has_extension;
+use super::video_process::*;
+
+/// Anything frames can be pulled out of, one numbered frame at a time.
+pub trait FrameSource {
+    fn frame_count(&mut self) -> Result<u64, Errors>;
+
+    /// Write frame `frame` as a png to `picture_path`.
+    fn extract_frame(&mut self, frame: u64, picture_path: &str) -> Result<(), Errors>;
+
+    /// Position of `frame` in the movie, sources without timing play at 24 fps.
+    fn frame_time_ms(&mut self, frame: u64) -> u64 {
+        frame * 1000 / 24
+    }
+
+    /// How long `frame` should stay up compared to an average frame of this source.
+    fn relative_duration(&self, _frame: u64) -> f64 {
+        1.0
+    }
+
+    /// Chapter marks of the movie, most sources have none.
+    fn chapters(&mut self) -> Vec<Chapter> {
+        Vec::new()
+    }
+
+    /// Whether the loop should reuse this source on the next tick instead of opening it again.
+    fn keep_open(&self) -> bool {
+        false
+    }
+}
+
+/// How movie files are decoded, Libav needs the `libav` cargo feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeBackend {
+    #[default]
+    Cli,
+    Libav,
+}
+impl DecodeBackend {
+    pub const ALL: [DecodeBackend; 2] = [DecodeBackend::Cli, DecodeBackend::Libav];
+}
+impl std::fmt::Display for DecodeBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DecodeBackend::Cli => "ffmpeg.exe",
+                DecodeBackend::Libav => "libav",
+            }
+        )
+    }
+}
+
+/// A movie file read through ffprobe.exe and ffmpeg.exe.
+pub struct FfmpegSource {
+    ffmpeg: String,
+    ffprobe: String,
+    movie_path: String,
+    frame_rate: Option<f64>,
+    chapters: Option<Vec<Chapter>>,
+}
+
+impl FfmpegSource {
+    pub fn new(movie_path: &str) -> Result<FfmpegSource, Errors> {
+        Ok(FfmpegSource {
+            ffmpeg: ffmpeg_exe()?,
+            ffprobe: ffprobe_exe()?,
+            movie_path: movie_path.to_owned(),
+            frame_rate: None,
+            chapters: None,
+        })
+    }
+}
+
+impl FrameSource for FfmpegSource {
+    fn frame_count(&mut self) -> Result<u64, Errors> {
+        generate_frame_count(&self.ffprobe, &self.movie_path)
+    }
+
+    fn extract_frame(&mut self, frame: u64, picture_path: &str) -> Result<(), Errors> {
+        generate_frame_picture(&self.ffmpeg, &self.movie_path, frame, picture_path)
+    }
+
+    fn frame_time_ms(&mut self, frame: u64) -> u64 {
+        if self.frame_rate.is_none() {
+            self.frame_rate = match generate_frame_rate(&self.ffprobe, &self.movie_path) {
+                Ok(rate) => Some(rate),
+                Err(e) => {
+                    log::warn!("Get frame rate error, use 24 fps. Error:{}", e);
+                    Some(24.0)
+                }
+            };
+        }
+        (frame as f64 * 1000.0 / self.frame_rate.unwrap_or(24.0)) as u64
+    }
+
+    fn chapters(&mut self) -> Vec<Chapter> {
+        if self.chapters.is_none() {
+            self.chapters = match generate_chapters(&self.ffprobe, &self.movie_path) {
+                Ok(chapters) => Some(chapters),
+                Err(e) => {
+                    log::warn!("Read chapters error! Error:{}", e);
+                    Some(Vec::new())
+                }
+            };
+        }
+        self.chapters.clone().unwrap_or_default()
+    }
+}
+
+pub const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "bmp", "tif", "tiff", "webp"];
+
+/// A folder of numbered pictures, frame N is the Nth file in natural order.
+pub struct ImageSequenceSource {
+    files: Vec<PathBuf>,
+}
+
+impl ImageSequenceSource {
+    /// `pattern` is a file name glob like "shot_*.jpg", empty takes every picture in the folder.
+    pub fn open(dir: &str, pattern: &str) -> Result<ImageSequenceSource, Errors> {
+        let pattern = if pattern.trim().is_empty() {
+            None
+        } else {
+            match glob::Pattern::new(pattern.trim()) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    return Err(Errors::SourceError(format!(
+                        "Bad image pattern \"{}\". {}",
+                        pattern, e
+                    )))
+                }
+            }
+        };
+        let extensions: Vec<String> = IMAGE_EXTENSIONS.iter().map(|e| e.to_string()).collect();
+
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let name = match path.file_name() {
+                Some(n) => n.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let wanted = match &pattern {
+                Some(p) => p.matches(&name),
+                None => has_extension(&path, &extensions),
+            };
+            if wanted {
+                files.push(path);
+            }
+        }
+        files.sort_by(|a, b| natord::compare(&a.to_string_lossy(), &b.to_string_lossy()));
+        if files.is_empty() {
+            return Err(Errors::SourceError(format!("No pictures found in {}", dir)));
+        }
+        Ok(ImageSequenceSource { files })
+    }
+}
+
+impl FrameSource for ImageSequenceSource {
+    fn frame_count(&mut self) -> Result<u64, Errors> {
+        Ok(self.files.len() as u64)
+    }
+
+    fn extract_frame(&mut self, frame: u64, picture_path: &str) -> Result<(), Errors> {
+        let file = match self.files.get(frame as usize) {
+            Some(f) => f,
+            None => return Err(Errors::FramePictureLost),
+        };
+        let picture = image::open(file)?;
+        picture.save_with_format(picture_path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationKind {
+    Gif,
+    Apng,
+    WebP,
+}
+
+impl AnimationKind {
+    /// Animated formats decoded in process, None for everything that needs ffmpeg.
+    pub fn detect(path: &Path) -> Option<AnimationKind> {
+        let ext = path.extension()?.to_string_lossy().to_lowercase();
+        match ext.as_str() {
+            "gif" => Some(AnimationKind::Gif),
+            "png" | "apng" => {
+                let decoder = PngDecoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+                if decoder.is_apng() {
+                    Some(AnimationKind::Apng)
+                } else {
+                    None
+                }
+            }
+            "webp" => {
+                let decoder = WebPDecoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+                if decoder.has_animation() {
+                    Some(AnimationKind::WebP)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// GIF, APNG or animated WebP decoded in Rust.
+/// Frames come out fully composited, disposal is handled by the decoder.
+pub struct AnimatedSource {
+    path: PathBuf,
+    kind: AnimationKind,
+    delays_ms: Vec<u32>,
+    mean_delay_ms: f64,
+    // decoder left after the last extracted frame, with the number of the frame it gives next
+    decoder: Option<(u64, Frames<'static>)>,
+}
+
+impl AnimatedSource {
+    pub fn open(path: &str, kind: AnimationKind) -> Result<AnimatedSource, Errors> {
+        let mut source = AnimatedSource {
+            path: PathBuf::from(path),
+            kind,
+            delays_ms: Vec::new(),
+            mean_delay_ms: 0.0,
+            decoder: None,
+        };
+        for frame in source.frames()? {
+            let (numer, denom) = frame?.delay().numer_denom_ms();
+            let delay = numer / denom.max(1);
+            // like browsers, treat "as fast as possible" as 100 ms
+            source.delays_ms.push(if delay <= 10 { 100 } else { delay });
+        }
+        if source.delays_ms.is_empty() {
+            return Err(Errors::SourceError(format!("No frames in {}", path)));
+        }
+        source.mean_delay_ms =
+            source.delays_ms.iter().map(|d| *d as f64).sum::<f64>() / source.delays_ms.len() as f64;
+        Ok(source)
+    }
+
+    fn frames(&self) -> Result<Frames<'static>, Errors> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        Ok(match self.kind {
+            AnimationKind::Gif => GifDecoder::new(reader)?.into_frames(),
+            AnimationKind::Apng => PngDecoder::new(reader)?.apng().into_frames(),
+            AnimationKind::WebP => WebPDecoder::new(reader)?.into_frames(),
+        })
+    }
+}
+
+impl FrameSource for AnimatedSource {
+    fn frame_count(&mut self) -> Result<u64, Errors> {
+        Ok(self.delays_ms.len() as u64)
+    }
+
+    fn extract_frame(&mut self, frame: u64, picture_path: &str) -> Result<(), Errors> {
+        // compositing needs every earlier frame, so decoding goes on from the last extracted
+        // frame and only starts over when playback went back
+        let (next, mut frames) = match self.decoder.take() {
+            Some((next, frames)) if next <= frame => (next, frames),
+            _ => (0, self.frames()?),
+        };
+        let picture = match frames.nth((frame - next) as usize) {
+            Some(f) => f?.into_buffer(),
+            None => return Err(Errors::FramePictureLost),
+        };
+        self.decoder = Some((frame + 1, frames));
+        picture.save_with_format(picture_path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+
+    fn frame_time_ms(&mut self, frame: u64) -> u64 {
+        self.delays_ms
+            .iter()
+            .take(frame as usize)
+            .map(|d| *d as u64)
+            .sum()
+    }
+
+    fn relative_duration(&self, frame: u64) -> f64 {
+        match self.delays_ms.get(frame as usize) {
+            Some(delay) if self.mean_delay_ms > 0.0 => *delay as f64 / self.mean_delay_ms,
+            _ => 1.0,
+        }
+    }
+
+    // opening decodes every frame for the delays, that is done once per movie
+    fn keep_open(&self) -> bool {
+        true
+    }
+}
+
+/// Pick the right reader for a playlist entry: folders are image sequences,
+/// animated pictures are decoded in process and everything else goes to ffmpeg.
+pub fn open_source(
+    movie_path: &str,
+    image_pattern: &str,
+    backend: DecodeBackend,
+) -> Result<Box<dyn FrameSource>, Errors> {
+    let path = Path::new(movie_path);
+    if path.is_dir() {
+        return Ok(Box::new(ImageSequenceSource::open(
+            movie_path,
+            image_pattern,
+        )?));
+    }
+    if let Some(kind) = AnimationKind::detect(path) {
+        return Ok(Box::new(AnimatedSource::open(movie_path, kind)?));
+    }
+    if backend == DecodeBackend::Libav {
+        #[cfg(feature = "libav")]
+        return Ok(Box::new(super::libav::LibavSource::open(movie_path)?));
+        #[cfg(not(feature = "libav"))]
+        log::warn!("Built without the libav feature, use ffmpeg.exe instead.");
+    }
+    Ok(Box::new(FfmpegSource::new(movie_path)?))
+}
@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+
+use super::config::Config;
+use super::errors::*;
+use super::prefetch::movie_key;
+use super::video_process::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub lines: Vec<String>,
+}
+
+fn read_text(path: &Path) -> Result<String, Errors> {
+    let bytes = std::fs::read(path)?;
+    let text = String::from_utf8_lossy(&bytes).to_string();
+    Ok(text.trim_start_matches('\u{feff}').to_owned())
+}
+
+// "01:02:03,456" or "1:02:03.45", the fraction is scaled to milliseconds
+fn parse_timestamp(stamp: &str) -> Option<u64> {
+    let stamp = stamp.trim().replace(',', ".");
+    let (clock, fraction) = match stamp.split_once('.') {
+        Some((c, f)) => (c.to_owned(), f.to_owned()),
+        None => (stamp.clone(), String::new()),
+    };
+    let mut secs: u64 = 0;
+    for part in clock.split(':') {
+        secs = secs * 60 + part.trim().parse::<u64>().ok()?;
+    }
+    // digits only, anything else is not a timestamp and would not slice on a char boundary
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let millis = match fraction.len() {
+        0 => 0,
+        n => {
+            let digits = &fraction[..n.min(3)];
+            digits.parse::<u64>().ok()? * 10u64.pow(3 - digits.len() as u32)
+        }
+    };
+    Some(secs * 1000 + millis)
+}
+
+// Drop markup like <i> or {\an8} and turn the text into display lines.
+fn clean_text(text: &str, open: char, close: char) -> Vec<String> {
+    let mut plain = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        if c == open {
+            in_tag = true;
+        } else if c == close && in_tag {
+            in_tag = false;
+        } else if !in_tag {
+            plain.push(c);
+        }
+    }
+    plain
+        .replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ")
+        .lines()
+        .map(|l| l.trim().to_owned())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+pub fn parse_srt(text: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    let text = text.replace("\r\n", "\n");
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|l| !l.contains("-->"));
+        let timing = match lines.next() {
+            Some(t) => t,
+            None => continue,
+        };
+        let (start, end) = match timing.split_once("-->") {
+            Some(pair) => pair,
+            None => continue,
+        };
+        // end may carry position hints after the time
+        let end = end.split_whitespace().next().unwrap_or("");
+        if let (Some(start_ms), Some(end_ms)) = (parse_timestamp(start), parse_timestamp(end)) {
+            let body: Vec<&str> = lines.collect();
+            cues.push(Cue {
+                start_ms,
+                end_ms,
+                lines: clean_text(&body.join("\n"), '<', '>'),
+            });
+        }
+    }
+    cues
+}
+
+pub fn parse_ass(text: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    // field positions from the [Events] Format line, these are the usual ones
+    let (mut start_at, mut end_at, mut text_at, mut fields) = (1, 2, 9, 10);
+    let mut in_events = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(format) = line.strip_prefix("Format:") {
+            let names: Vec<String> = format.split(',').map(|n| n.trim().to_lowercase()).collect();
+            fields = names.len();
+            for (i, name) in names.iter().enumerate() {
+                match name.as_str() {
+                    "start" => start_at = i,
+                    "end" => end_at = i,
+                    "text" => text_at = i,
+                    _ => {}
+                }
+            }
+        } else if let Some(dialogue) = line.strip_prefix("Dialogue:") {
+            // text is last and may contain commas itself
+            let parts: Vec<&str> = dialogue.splitn(fields, ',').collect();
+            if parts.len() <= text_at.max(start_at).max(end_at) {
+                continue;
+            }
+            if let (Some(start_ms), Some(end_ms)) = (
+                parse_timestamp(parts[start_at]),
+                parse_timestamp(parts[end_at]),
+            ) {
+                cues.push(Cue {
+                    start_ms,
+                    end_ms,
+                    lines: clean_text(parts[text_at], '{', '}'),
+                });
+            }
+        }
+    }
+    cues
+}
+
+pub fn load_subtitle_file(path: &Path) -> Result<Vec<Cue>, Errors> {
+    let text = read_text(path)?;
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let mut cues = match ext.as_str() {
+        "ass" | "ssa" => parse_ass(&text),
+        _ => parse_srt(&text),
+    };
+    cues.sort_by_key(|c| c.start_ms);
+    Ok(cues)
+}
+
+/// A .srt or .ass file next to the movie with the same name.
+pub fn find_sidecar(movie_path: &str) -> Option<PathBuf> {
+    let movie = Path::new(movie_path);
+    ["srt", "ass", "ssa"]
+        .iter()
+        .map(|ext| movie.with_extension(ext))
+        .find(|p| p.is_file())
+}
+
+// Embedded tracks are converted to srt once and kept in the profile's subtitles folder,
+// named by the whole movie path so movies with the same file name in different folders
+// keep their own.
+fn embedded_subtitle(conf: &Config, movie_path: &str, stream: u32) -> Result<PathBuf, Errors> {
+    let mut out = conf.state_file("subtitles");
+    std::fs::create_dir_all(&out)?;
+    out.push(format!("{}.{}.srt", movie_key(movie_path), stream));
+    if !out.is_file() {
+        extract_subtitle_stream(
+            &ffmpeg_exe()?,
+            movie_path,
+            stream,
+            &out.display().to_string(),
+        )?;
+    }
+    Ok(out)
+}
+
+/// Cues for a movie: an explicit file first, then the chosen embedded stream, then a sidecar.
+pub fn load_cues(conf: &Config, movie_path: &str) -> Result<Vec<Cue>, Errors> {
+    let subtitle_path = conf.get_subtitle_path();
+    let subtitle_stream = conf.get_subtitle_stream();
+    if !subtitle_path.is_empty() {
+        return load_subtitle_file(Path::new(&subtitle_path));
+    }
+    if subtitle_stream >= 0 {
+        let path = embedded_subtitle(conf, movie_path, subtitle_stream as u32)?;
+        return load_subtitle_file(&path);
+    }
+    match find_sidecar(movie_path) {
+        Some(path) => load_subtitle_file(&path),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Cue showing at `time_ms`, the latest starting one wins when they overlap.
+pub fn cue_at(cues: &[Cue], time_ms: u64) -> Option<&Cue> {
+    cues.iter()
+        .rev()
+        .find(|c| c.start_ms <= time_ms && time_ms < c.end_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamp_scales_the_fraction() {
+        assert_eq!(parse_timestamp("01:02:03,456"), Some(3_723_456));
+        assert_eq!(parse_timestamp("1:02:03.45"), Some(3_723_450));
+        assert_eq!(parse_timestamp(" 0:00:01.5 "), Some(1_500));
+        assert_eq!(parse_timestamp("00:00:01"), Some(1_000));
+        assert_eq!(parse_timestamp("00:00:01,4567"), Some(1_456));
+    }
+
+    #[test]
+    fn parse_timestamp_rejects_other_text() {
+        assert_eq!(parse_timestamp("00:00:01,é5"), None);
+        assert_eq!(parse_timestamp("00:00:01,٤٥٦"), None);
+        assert_eq!(parse_timestamp("00:xx:01"), None);
+        assert_eq!(parse_timestamp(""), None);
+    }
+}
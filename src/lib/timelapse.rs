@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::config::Config;
+use super::errors::*;
+use super::history::load_history;
+use super::prerender::for_each_range_frame;
+use super::render::{finish_frame, FrameInfo};
+use super::source::{open_source, DecodeBackend, FrameSource};
+use super::video_process::{encode_sequence, ffmpeg_exe};
+
+/// Which frames go into a timelapse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimelapseInput {
+    /// The last `count` frames of the display history, 0 for all of it.
+    History { count: usize },
+    /// Every `step`th frame of one movie in [first_frame, end_frame), end 0 runs to the end.
+    Range {
+        movie_path: String,
+        first_frame: u64,
+        end_frame: u64,
+        step: u64,
+    },
+}
+
+// Pictures the desktop showed, rendered again where the output folder no longer has them.
+fn collect_history(
+    conf: &Config,
+    count: usize,
+    staging: &Path,
+    progress: &mut impl FnMut(u64, u64),
+) -> Result<u64, Errors> {
+    let mut entries = load_history(conf);
+    if count > 0 && entries.len() > count {
+        entries.drain(..entries.len() - count);
+    }
+    let total = entries.len() as u64;
+    // history switches between movies, each is opened and probed once
+    let mut opened: HashMap<String, (Box<dyn FrameSource>, u64)> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let target = staging.join(format!("{:08}.png", i));
+        if Path::new(&entry.picture_path).is_file() {
+            fs::copy(&entry.picture_path, &target)?;
+        } else {
+            if !opened.contains_key(&entry.movie_path) {
+                let mut source = open_source(
+                    &entry.movie_path,
+                    &conf.get_image_pattern(),
+                    conf.get_decode_backend(),
+                )?;
+                let total_frame = source.frame_count()?;
+                opened.insert(entry.movie_path.clone(), (source, total_frame));
+            }
+            if let Some((source, total_frame)) = opened.get_mut(&entry.movie_path) {
+                let target_path = target.display().to_string();
+                let info = FrameInfo::from_source(
+                    conf,
+                    source.as_mut(),
+                    &entry.movie_path,
+                    entry.frame,
+                    *total_frame,
+                );
+                source.extract_frame(entry.frame, &target_path)?;
+                finish_frame(&target_path, conf, &info)?;
+            }
+        }
+        progress(i as u64 + 1, total);
+    }
+    Ok(total)
+}
+
+fn collect_range(
+    conf: &Config,
+    movie_path: &str,
+    first_frame: u64,
+    end_frame: u64,
+    step: u64,
+    staging: &Path,
+    progress: &mut impl FnMut(u64, u64),
+) -> Result<u64, Errors> {
+    let step = step.max(1);
+    let mut source = open_source(movie_path, "", DecodeBackend::Cli)?;
+    let total_frame = source.frame_count()?;
+    let end_frame = match end_frame {
+        0 => total_frame,
+        n => n.min(total_frame),
+    };
+    let expected = end_frame.saturating_sub(first_frame).div_ceil(step);
+    let raw = staging.join("raw");
+    let mut done = 0;
+    for_each_range_frame(
+        movie_path,
+        first_frame,
+        end_frame,
+        step,
+        0,
+        &raw,
+        |index, picture| {
+            if index >= expected {
+                return Ok(());
+            }
+            let target = staging.join(format!("{:08}.png", index));
+            let info = FrameInfo::from_source(
+                conf,
+                source.as_mut(),
+                movie_path,
+                first_frame + index * step,
+                total_frame,
+            );
+            fs::rename(picture, &target)?;
+            finish_frame(&target.display().to_string(), conf, &info)?;
+            done += 1;
+            progress(done, expected);
+            Ok(())
+        },
+    )?;
+    Ok(done)
+}
+
+/// Encode frames into an MP4 or GIF at `fps`, drawn with the wallpaper's own output
+/// processing. `progress` is called with (done, count) while the frames are prepared.
+/// Returns the number of frames in the video.
+pub fn export_timelapse(
+    conf: &Config,
+    input: &TimelapseInput,
+    fps: f64,
+    out_path: &str,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<u64, Errors> {
+    let staging: PathBuf = conf.state_file("timelapse_frames");
+    if staging.is_dir() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    let count = match input {
+        TimelapseInput::History { count } => {
+            collect_history(conf, *count, &staging, &mut progress)?
+        }
+        TimelapseInput::Range {
+            movie_path,
+            first_frame,
+            end_frame,
+            step,
+        } => collect_range(
+            conf,
+            movie_path,
+            *first_frame,
+            *end_frame,
+            *step,
+            &staging,
+            &mut progress,
+        )?,
+    };
+    if count == 0 {
+        return Err(Errors::ExportError(String::from("no frames to encode")));
+    }
+
+    // frames of different movies may differ in size, the first one sets the video size
+    let (width, height) = image::image_dimensions(staging.join(format!("{:08}.png", 0)))?;
+    encode_sequence(
+        &ffmpeg_exe()?,
+        &staging.join("%08d.png").display().to_string(),
+        fps,
+        width,
+        height,
+        out_path,
+    )?;
+    fs::remove_dir_all(&staging)?;
+    Ok(count)
+}
@@ -1,8 +1,31 @@
 use super::errors::*;
 use super::utils::*;
 
+use std::env;
 use std::os::windows::process::CommandExt;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
+
+fn tool_path(exe: &str) -> Result<String, Errors> {
+    match env::current_dir() {
+        Ok(mut path) => {
+            path.push("ffmpeg");
+            path.push(exe);
+            Ok(path.display().to_string())
+        }
+        Err(e) => {
+            log::error!("Get {} path error! Error:{}", exe, e);
+            Err(Errors::InnerIOError(e))
+        }
+    }
+}
+
+pub fn ffmpeg_exe() -> Result<String, Errors> {
+    tool_path("ffmpeg.exe").map_err(|_| Errors::FfmpegLost)
+}
+
+pub fn ffprobe_exe() -> Result<String, Errors> {
+    tool_path("ffprobe.exe").map_err(|_| Errors::FfprobeLost)
+}
 pub fn generate_frame_count(ffprobe: &String, moviepath: &String) -> Result<u64, Errors> {
     let get_frame_count = ffprobe.to_owned() + &String::from(" -v error -select_streams v:0 -count_packets -show_entries stream=nb_read_packets -of csv=p=0 ") + moviepath ;
     let child = if cfg!(target_os = "windows") {
@@ -67,3 +90,237 @@ pub fn generate_frame_picture(
     }
     Ok(())
 }
+
+pub fn generate_frame_rate(ffprobe: &str, movie_path: &str) -> Result<f64, Errors> {
+    let get_frame_rate = ffprobe.to_owned()
+        + &String::from(
+            " -v error -select_streams v:0 -show_entries stream=avg_frame_rate -of csv=p=0 ",
+        )
+        + movie_path;
+    let child = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", &get_frame_rate])
+            .creation_flags(0x08000000)
+            .stdout(Stdio::piped())
+            .spawn()?
+    } else {
+        return Err(Errors::OSTypeError);
+    };
+    let output = child.wait_with_output()?;
+    let rate = String::from_utf8_lossy(&output.stdout).to_string();
+    let rate = strip_trailing_newline(&rate);
+    // ffprobe answers with a fraction like 24000/1001
+    let (numer, denom) = rate.split_once('/').unwrap_or((rate, "1"));
+    match (numer.parse::<f64>(), denom.parse::<f64>()) {
+        (Ok(n), Ok(d)) if n > 0.0 && d > 0.0 => Ok(n / d),
+        _ => Err(Errors::FrameCountError(format!(
+            "Unknown frame rate \"{}\"",
+            rate
+        ))),
+    }
+}
+
+/// The select filter keeping frames `first_frame + k * step` below `end_frame`,
+/// an end of 0 runs to the end of the movie.
+pub(crate) fn range_select_filter(first_frame: u64, end_frame: u64, step: u64) -> String {
+    let end = match end_frame {
+        0 => String::new(),
+        end => format!(r"*lt(n\,{})", end),
+    };
+    format!(
+        r"select=gte(n\,{first})*not(mod(n-{first}\,{step})){end}",
+        first = first_frame,
+        step = step.max(1),
+        end = end
+    )
+}
+
+/// Start one ffmpeg pass writing every `step`th frame from `first_frame` up to `end_frame`,
+/// 0 for the end of the movie. Output files are numbered from `start_number` in the printf
+/// style `out_pattern`.
+pub fn spawn_frame_range(
+    ffmpeg: &str,
+    movie_path: &str,
+    first_frame: u64,
+    end_frame: u64,
+    step: u64,
+    start_number: u64,
+    out_pattern: &str,
+) -> Result<Child, Errors> {
+    let get_frames = ffmpeg.to_owned()
+        + &String::from(" -y -v error -i ")
+        + movie_path
+        + &format!(
+            " -vf {} -vsync 0 -start_number {} -f image2 ",
+            range_select_filter(first_frame, end_frame, step),
+            start_number
+        )
+        + out_pattern;
+    log::info!("command : {}", &get_frames);
+    if cfg!(target_os = "windows") {
+        let child = Command::new("cmd")
+            .args(["/C", &get_frames])
+            .creation_flags(0x08000000)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        Ok(child)
+    } else {
+        Err(Errors::OSTypeError)
+    }
+}
+
+pub fn extract_subtitle_stream(
+    ffmpeg: &str,
+    movie_path: &str,
+    stream: u32,
+    subtitle_path: &str,
+) -> Result<(), Errors> {
+    let get_subtitle = ffmpeg.to_owned()
+        + &String::from(" -y -v error -i ")
+        + movie_path
+        + &String::from(" -map 0:s:")
+        + &stream.to_string()
+        + &String::from(" -f srt ")
+        + subtitle_path;
+    log::info!("command : {}", &get_subtitle);
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", &get_subtitle])
+            .creation_flags(0x08000000)
+            .stdout(Stdio::piped())
+            .output()?
+    } else {
+        return Err(Errors::OSTypeError);
+    };
+    if !output.status.success() {
+        return Err(Errors::FfmpegRunError(format!(
+            "Extract subtitle stream {} failed. Finished with:{}",
+            stream, output.status
+        )));
+    }
+    Ok(())
+}
+
+/// Encode numbered pictures into a video. A .gif output gets its own palette, anything
+/// else is H.264. Every picture is fitted into `width` x `height`.
+pub fn encode_sequence(
+    ffmpeg: &str,
+    pattern: &str,
+    fps: f64,
+    width: u32,
+    height: u32,
+    out_path: &str,
+) -> Result<(), Errors> {
+    // H.264 wants even sizes
+    let (width, height) = (width / 2 * 2, height / 2 * 2);
+    let fit = format!(
+        "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2",
+        w = width,
+        h = height
+    );
+    let codec = match out_path.to_lowercase().ends_with(".gif") {
+        true => format!(
+            " -vf \"{},split[a][b];[a]palettegen[p];[b][p]paletteuse\" ",
+            fit
+        ),
+        false => format!(" -vf \"{}\" -c:v libx264 -pix_fmt yuv420p ", fit),
+    };
+    let encode = ffmpeg.to_owned()
+        + &format!(" -y -v error -framerate {} -i ", fps)
+        + pattern
+        + &codec
+        + out_path;
+    log::info!("command : {}", &encode);
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", &encode])
+            .creation_flags(0x08000000)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()?
+    } else {
+        return Err(Errors::OSTypeError);
+    };
+    if !output.status.success() {
+        return Err(Errors::FfmpegRunError(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Chapter {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub title: String,
+}
+
+fn seconds_to_ms(value: &serde_json::Value) -> u64 {
+    // ffprobe writes times as strings like "12.345000"
+    let secs = match value {
+        serde_json::Value::String(s) => s.parse::<f64>().unwrap_or(0.0),
+        serde_json::Value::Number(n) => n.as_f64().unwrap_or(0.0),
+        _ => 0.0,
+    };
+    (secs * 1000.0).max(0.0) as u64
+}
+
+pub fn generate_chapters(ffprobe: &str, movie_path: &str) -> Result<Vec<Chapter>, Errors> {
+    let get_chapters =
+        ffprobe.to_owned() + &String::from(" -v error -show_chapters -of json ") + movie_path;
+    let child = if cfg!(target_os = "windows") {
+        Command::new("cmd")
+            .args(["/C", &get_chapters])
+            .creation_flags(0x08000000)
+            .stdout(Stdio::piped())
+            .spawn()?
+    } else {
+        return Err(Errors::OSTypeError);
+    };
+    let output = child.wait_with_output()?;
+    let json: serde_json::Value = match serde_json::from_slice(&output.stdout) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(Errors::FfmpegRunError(format!(
+                "Read chapters from ffprobe failed. {}",
+                e
+            )))
+        }
+    };
+    let mut chapters: Vec<Chapter> = match json["chapters"].as_array() {
+        Some(list) => list
+            .iter()
+            .map(|c| Chapter {
+                start_ms: seconds_to_ms(&c["start_time"]),
+                end_ms: seconds_to_ms(&c["end_time"]),
+                title: c["tags"]["title"].as_str().unwrap_or("").to_owned(),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    chapters.sort_by_key(|c| c.start_ms);
+    Ok(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_select_starts_at_first_frame() {
+        assert_eq!(
+            range_select_filter(10, 0, 4),
+            r"select=gte(n\,10)*not(mod(n-10\,4))"
+        );
+        assert_eq!(
+            range_select_filter(10, 30, 4),
+            r"select=gte(n\,10)*not(mod(n-10\,4))*lt(n\,30)"
+        );
+        assert_eq!(
+            range_select_filter(0, 0, 0),
+            r"select=gte(n\,0)*not(mod(n-0\,1))"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::errors::*;
+use super::playlist::{has_extension, parse_extensions, scan_folder};
+use super::video_process::generate_frame_count;
+
+#[derive(Default)]
+struct WatchState {
+    /// Movies that finished copying, with the frame count ffprobe read.
+    movies: BTreeMap<String, u64>,
+    initial_scan_done: bool,
+}
+
+/// Keeps the movie list of a folder up to date while the daemon runs.
+/// New files only join once their size stopped changing and ffprobe can read them.
+pub struct FolderWatcher {
+    folder: String,
+    state: Arc<Mutex<WatchState>>,
+    // dropping the watcher ends the background thread
+    _watcher: RecommendedWatcher,
+}
+
+impl FolderWatcher {
+    pub fn start(
+        folder: &str,
+        recursive: bool,
+        extensions: &str,
+        settle_secs: u64,
+        ffprobe: &str,
+    ) -> Result<FolderWatcher, Errors> {
+        let (tx, rx) = channel();
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(w) => w,
+            Err(e) => return Err(Errors::WatchFolderError(e.to_string())),
+        };
+        let mode = match recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+        if let Err(e) = watcher.watch(Path::new(folder), mode) {
+            return Err(Errors::WatchFolderError(e.to_string()));
+        }
+
+        let existing = scan_folder(folder, recursive, extensions)?;
+        let state = Arc::new(Mutex::new(WatchState::default()));
+        let worker = Worker {
+            state: state.clone(),
+            extensions: parse_extensions(extensions),
+            settle: Duration::from_secs(settle_secs),
+            ffprobe: ffprobe.to_owned(),
+            pending: HashMap::new(),
+            failed: HashMap::new(),
+        };
+        thread::spawn(move || worker.run(rx, existing));
+        log::info!("Start watching folder {}", folder);
+
+        Ok(FolderWatcher {
+            folder: folder.to_owned(),
+            state,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn folder(&self) -> String {
+        self.folder.clone()
+    }
+
+    /// Ready movies in path order, None until the files found at start have been probed.
+    pub fn movies(&self) -> Option<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        if !state.initial_scan_done {
+            return None;
+        }
+        Some(state.movies.keys().cloned().collect())
+    }
+
+    pub fn frame_count(&self, movie_path: &str) -> Option<u64> {
+        self.state.lock().unwrap().movies.get(movie_path).copied()
+    }
+}
+
+struct Worker {
+    state: Arc<Mutex<WatchState>>,
+    extensions: Vec<String>,
+    settle: Duration,
+    ffprobe: String,
+    /// Files still being written: last seen size and when it last changed.
+    pending: HashMap<PathBuf, (u64, Instant)>,
+    /// Files ffprobe could not read, with their size and modified time at that attempt.
+    failed: HashMap<PathBuf, (u64, SystemTime)>,
+}
+
+impl Worker {
+    fn run(mut self, rx: Receiver<notify::Result<notify::Event>>, existing: Vec<String>) {
+        // files already in the folder count as settled straight away
+        let settled = Instant::now()
+            .checked_sub(self.settle)
+            .unwrap_or_else(Instant::now);
+        for path in existing {
+            let path = normalize(Path::new(&path));
+            let size = file_size(&path).unwrap_or(0);
+            self.pending.insert(path, (size, settled));
+        }
+        self.check_pending();
+        self.state.lock().unwrap().initial_scan_done = true;
+
+        loop {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(Ok(event)) => {
+                    for path in event.paths {
+                        self.on_change(normalize(&path));
+                    }
+                }
+                Ok(Err(e)) => log::warn!("Watch folder event error! Error:{}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            self.check_pending();
+        }
+    }
+
+    fn on_change(&mut self, path: PathBuf) {
+        if !has_extension(&path, &self.extensions) {
+            return;
+        }
+        if path.is_file() {
+            let size = file_size(&path).unwrap_or(0);
+            self.failed.remove(&path);
+            self.pending.insert(path, (size, Instant::now()));
+        } else {
+            let key = path.display().to_string();
+            if self.state.lock().unwrap().movies.remove(&key).is_some() {
+                log::info!("Movie removed from watch folder: {}", key);
+            }
+            self.pending.remove(&path);
+            self.failed.remove(&path);
+        }
+    }
+
+    fn check_pending(&mut self) {
+        // a file ffprobe could not read is tried again once it changed, a copy may have
+        // paused long enough to look settled; a broken file stays out until it is replaced
+        let mut changed = Vec::new();
+        self.failed.retain(|path, stamp| match file_stamp(path) {
+            Some(now) if now == *stamp => true,
+            Some(_) => {
+                changed.push(path.clone());
+                false
+            }
+            None => false,
+        });
+        for path in changed {
+            let size = file_size(&path).unwrap_or(0);
+            self.pending.insert(path, (size, Instant::now()));
+        }
+
+        let mut ready = Vec::new();
+        self.pending.retain(|path, (size, changed)| {
+            let now_size = match file_size(path) {
+                Some(s) => s,
+                None => return false,
+            };
+            if now_size != *size {
+                *size = now_size;
+                *changed = Instant::now();
+                return true;
+            }
+            if now_size == 0 || changed.elapsed() < self.settle {
+                return true;
+            }
+            ready.push(path.clone());
+            false
+        });
+
+        for path in ready {
+            let key = path.display().to_string();
+            match generate_frame_count(&self.ffprobe, &key) {
+                Ok(n) => {
+                    log::info!("Movie added from watch folder: {} frames:{}", key, n);
+                    self.state.lock().unwrap().movies.insert(key, n);
+                }
+                Err(e) => {
+                    log::warn!("Probe {} failed, retry when it changes. Error:{}", key, e);
+                    if let Some(stamp) = file_stamp(&path) {
+                        self.failed.insert(path, stamp);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Events and the folder scan name the same file differently, relative or with other
+// separators, so paths are made absolute before they are compared or stored. The folder is
+// resolved rather than the file, which may be gone already.
+fn normalize(path: &Path) -> PathBuf {
+    let full = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => match std::fs::canonicalize(dir) {
+            Ok(dir) => dir.join(name),
+            Err(_) => path.to_path_buf(),
+        },
+        _ => path.to_path_buf(),
+    };
+    // canonicalize gives \\?\ paths, ffmpeg and the config want plain ones
+    let text = full.display().to_string();
+    match text.strip_prefix(r"\\?\") {
+        Some(plain) => PathBuf::from(plain),
+        None => full,
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_file() => Some(meta.len()),
+        _ => None,
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_file() => Some((meta.len(), meta.modified().ok()?)),
+        _ => None,
+    }
+}
@@ -7,14 +7,26 @@ use fast_log::plugin::file_split::RollingType;
 use fast_log::plugin::packer::GZipPacker;
 use log::LevelFilter;
 
+mod cli;
 mod do_wallpaper;
 mod run_gui;
 
-use utillib::{config, Errors};
+use utillib::{config, control, profile, Errors};
 
 pub fn main() -> Result<(), Errors> {
+    // every profile has its own config, state and instance
+    let (name, args) = profile::profile_args(std::env::args().skip(1).collect());
+    if let Some(name) = name {
+        profile::set_profile(&name)?;
+    }
+
+    // subcommands run here too, slowmovie.exe is the console that shows their output
+    if let Some(result) = cli::run(&args) {
+        return result;
+    }
+
     // prevent muti instances of program
-    let lock = named_lock::NamedLock::create("slowmovie")?;
+    let lock = named_lock::NamedLock::create(&profile::lock_name())?;
     let _guard = lock.try_lock();
     match _guard {
         Ok(_) => {
@@ -22,8 +34,10 @@ pub fn main() -> Result<(), Errors> {
         }
         Err(e) => match e {
             named_lock::Error::WouldBlock => {
-                println!("Find brothers!");
-                return run_gui::run();
+                // the running instance shows its window or the movie, we are done
+                log::info!("Another instance is running, hand the arguments over.");
+                let command = cli::handoff_command(&args);
+                return control::push_command(&config::load(), command);
             }
             _ => {
                 println!("Other Error!");
@@ -38,7 +52,7 @@ pub fn main() -> Result<(), Errors> {
             .chan_len(Some(100000))
             .level(LevelFilter::Debug)
             .file_split(
-                "logs/", // current_exe dir
+                &profile::log_dir(), // current_exe dir
                 LogSize::MB(5),
                 RollingType::KeepNum(5),
                 GZipPacker {},
@@ -49,6 +63,11 @@ pub fn main() -> Result<(), Errors> {
     //init exit flag
     let mut conf = config::load();
     conf.set_exit_flag(false);
+    if let control::ControlCommand::Open { movie_path } = cli::handoff_command(&args) {
+        if movie_path != conf.get_movie_path() {
+            conf.jump_to(movie_path, 0);
+        }
+    }
     config::save_config(&conf);
 
     //gui
@@ -2,7 +2,7 @@ use std::env;
 use std::os::windows::process::CommandExt;
 use std::process::Command;
 
-use utillib::{utils::*, Errors};
+use utillib::{profile::profile_command, utils::*, Errors};
 
 pub fn run() -> Result<(), Errors> {
     let gui = match env::current_exe() {
@@ -19,7 +19,7 @@ pub fn run() -> Result<(), Errors> {
     };
     let child_status = if cfg!(target_os = "windows") {
         Command::new("cmd")
-            .args(["/C", &gui])
+            .args(["/C", &profile_command(&gui)])
             .creation_flags(0x08000000)
             .status()
             .expect("failed to execute gui.exe")